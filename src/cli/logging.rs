@@ -0,0 +1,73 @@
+//! # Logging
+//!
+//! What the training manager does is logged with `tracing` on the standard error, so
+//! the reports printed on the standard output, JSON ones included, stay parseable.
+//!
+//! The level is `info` by default, raised by `-v` and lowered by `-q`; the libraries
+//! underneath only log their warnings and errors. `RUST_LOG`, when set, replaces both
+//! with its own directives, such as `training_manager=debug,reqwest=debug`.
+
+use clap::ValueEnum;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+/// How log events are written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One human readable line per event.
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of the event and of its spans.
+    Json,
+}
+
+/// The level of the events kept, `info` moved up by `verbose` and down by `quiet` steps.
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [LevelFilter::OFF, LevelFilter::ERROR, LevelFilter::WARN, LevelFilter::INFO, LevelFilter::DEBUG, LevelFilter::TRACE];
+    let index = (3 + i16::from(verbose) - i16::from(quiet)).clamp(0, 5);
+    LEVELS[index as usize]
+}
+
+/// The filter directives for `level`: `level` for the training manager, at most
+/// warnings for the libraries it uses.
+fn default_directives(level: LevelFilter) -> String {
+    format!("{},{}={}", level.min(LevelFilter::WARN), env!("CARGO_CRATE_NAME"), level)
+}
+
+/// Installs the global subscriber, filtering events with `RUST_LOG` if it is set, or
+/// by `level` otherwise.
+pub fn init(format: LogFormat, level: LevelFilter) {
+    let directives = std::env::var(EnvFilter::DEFAULT_ENV).ok()
+        .filter(|directives| !directives.is_empty())
+        .unwrap_or_else(|| default_directives(level));
+    let filter = EnvFilter::builder().parse_lossy(directives);
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0, 0), LevelFilter::INFO);
+        assert_eq!(level(1, 0), LevelFilter::DEBUG);
+        assert_eq!(level(5, 0), LevelFilter::TRACE);
+        assert_eq!(level(0, 1), LevelFilter::WARN);
+        assert_eq!(level(0, 2), LevelFilter::ERROR);
+        assert_eq!(level(0, 9), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn test_default_directives() {
+        assert_eq!(default_directives(LevelFilter::DEBUG), "warn,training_manager=debug");
+        assert_eq!(default_directives(LevelFilter::ERROR), "error,training_manager=error");
+    }
+}
@@ -0,0 +1,544 @@
+//! # Command Line Interface
+//!
+//! Parses the command line and runs the selected subcommand.
+//!
+//! Exit codes are chosen so cron jobs and CI pipelines can react to the outcome:
+//! `0` on success, `1` on error and `2` when `plan` or `prune --dry-run` finds
+//! changes to apply.
+
+pub mod logging;
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use clap::{ArgAction, Parser, Subcommand};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use crate::config::{self, ConfigFile, calendar, validate};
+use crate::config::validate::Severity;
+use crate::daemon::Daemon;
+use crate::galaxy::{self, ClientSettings, GalaxyError};
+use crate::manager::{DEFAULT_CONCURRENCY, GroupUpdatesError, InstanceFailure, InstanceFailuresError, TrainingManager};
+use crate::manager::plan::Plan;
+use crate::manager::prune::PrunePlan;
+use logging::LogFormat;
+
+#[derive(Debug, Parser)]
+#[command(name = "training-manager", version, about = "Assigns the training role to Galaxy groups based on a schedule")]
+pub struct Cli {
+    /// Galaxy URL, with its path if Galaxy is served under one, defaults to $GALAXY_URL,
+    /// https://$GALAXY_HOSTNAME or the url of the [galaxy] section of the configuration
+    #[arg(long, global = true)]
+    pub galaxy_url: Option<String>,
+
+    /// Galaxy admin API key, defaults to $GALAXY_ADMIN_API_KEY, the content of the file at
+    /// $GALAXY_ADMIN_API_KEY_FILE or the [galaxy] section of the configuration
+    #[arg(long, global = true)]
+    pub api_key: Option<String>,
+
+    /// Seconds to wait for a connection to Galaxy
+    #[arg(long, global = true, default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for a Galaxy response
+    #[arg(long, global = true, default_value_t = 60)]
+    pub request_timeout: u64,
+
+    /// Number of times a failed idempotent Galaxy request is retried
+    #[arg(long, global = true, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Maximum number of groups updated at the same time
+    #[arg(long, global = true, default_value_t = DEFAULT_CONCURRENCY)]
+    pub concurrency: usize,
+
+    /// Maximum number of Galaxy requests per second, unlimited by default
+    #[arg(long, global = true)]
+    pub rate_limit: Option<f64>,
+
+    /// Number of Galaxy requests sent at once before the rate limit applies
+    #[arg(long, global = true, default_value_t = 10)]
+    pub burst: u32,
+
+    /// PEM file of certificate authorities to trust for Galaxy, in addition to the system ones
+    #[arg(long, global = true)]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Do not verify the TLS certificate of Galaxy, for local testing only
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    /// Log more, repeat for even more (-vv)
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Log less, repeat for errors only (-qq)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Format of the logs written on the standard error
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Apply the configuration to Galaxy
+    Apply {
+        config: String,
+        /// Print the applied changes as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the changes `apply` would make, without modifying Galaxy
+    Plan {
+        config: String,
+        /// Print the changes as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check the configuration file without contacting Galaxy
+    Validate {
+        config: String,
+        /// Also read the calendars of the configuration, fetching its http(s) feeds
+        #[arg(long)]
+        fetch_calendars: bool,
+    },
+    /// Show which groups are in training now and who is in them
+    Status {
+        config: String,
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete the groups created by `apply` that are no longer in the configuration
+    Prune {
+        config: String,
+        /// Show the groups that would be deleted, without modifying Galaxy
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the deleted groups as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Render the schedule as an iCalendar file, without contacting Galaxy
+    Export {
+        config: String,
+        /// Write the calendar to this file instead of the standard output
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Keep running and apply the configuration whenever a schedule item starts or ends,
+    /// or the configuration file changes
+    Daemon {
+        config: String,
+        /// Seconds between two safety reconciliations
+        #[arg(long, default_value_t = 3600)]
+        resync_interval: u64,
+    },
+}
+
+/// Outcome of a command, mapped to the process exit code.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExitStatus {
+    Success,
+    Error,
+    Drift,
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> ExitCode {
+        match status {
+            ExitStatus::Success => ExitCode::from(0),
+            ExitStatus::Error => ExitCode::from(1),
+            ExitStatus::Drift => ExitCode::from(2),
+        }
+    }
+}
+
+/// Prints an error, followed by what the user can do about the Galaxy errors behind it.
+pub fn report_error(error: &anyhow::Error) {
+    eprintln!("Error: {:#}", error);
+    for hint in hints(error) {
+        eprintln!("Hint: {}", hint);
+    }
+}
+
+/// The hints of the Galaxy errors behind `error`, including those of every failed
+/// instance and group.
+fn hints(error: &anyhow::Error) -> BTreeSet<&'static str> {
+    let mut errors = vec![error];
+    if let Some(instances) = error.downcast_ref::<InstanceFailuresError>() {
+        errors.extend(instances.failures.iter().map(|failure| &failure.error));
+    }
+    let updates = errors.iter()
+        .filter_map(|error| error.downcast_ref::<GroupUpdatesError>())
+        .flat_map(|updates| updates.failures.iter().map(|failure| &failure.error))
+        .collect::<Vec<_>>();
+    errors.extend(updates);
+    errors.into_iter()
+        .filter_map(|error| error.chain().find_map(|cause| cause.downcast_ref::<GalaxyError>()))
+        .filter_map(GalaxyError::hint)
+        .collect()
+}
+
+/// Logs an error with the hints of the Galaxy errors behind it, for the errors of
+/// long-running commands, which go to the logs.
+pub fn log_error(error: &anyhow::Error) {
+    let hints = hints(error).into_iter().collect::<Vec<_>>();
+    if hints.is_empty() {
+        tracing::error!("{:#}", error);
+    } else {
+        tracing::error!(hint = hints.join("; "), "{:#}", error);
+    }
+}
+
+/// Reports the time spent waiting for the Galaxy rate limit, if requests had to wait.
+fn report_rate_limit(training_manager: &TrainingManager) {
+    let metrics = training_manager.rate_limit_metrics();
+    if metrics.throttled_requests > 0 {
+        tracing::info!(
+            instance = training_manager.instance(),
+            throttled_requests = metrics.throttled_requests,
+            waited_secs = metrics.waited.as_secs_f64(),
+            "{}", metrics,
+        );
+    }
+}
+
+/// Runs `command` on every Galaxy, one after the other, and prints what it returned:
+/// as is with a single Galaxy, under the name of each instance with named instances.
+///
+/// A named instance failing does not stop the others; the failures are returned
+/// together in an `InstanceFailuresError` once the reports of the others are printed.
+async fn run_on_instances<T: Serialize>(
+    training_managers: &[TrainingManager],
+    json: bool,
+    command: impl AsyncFn(&TrainingManager) -> Result<T>,
+    print: impl Fn(&T),
+) -> Result<Vec<T>> {
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+    for training_manager in training_managers {
+        let result = command(training_manager).await;
+        report_rate_limit(training_manager);
+        match (training_manager.instance(), result) {
+            (instance, Ok(report)) => reports.push((instance, report)),
+            (Some(instance), Err(error)) => failures.push(InstanceFailure { instance: instance.to_string(), error }),
+            (None, Err(error)) => return Err(error),
+        }
+    }
+    if json {
+        match reports.as_slice() {
+            [(None, report)] => println!("{}", serde_json::to_string_pretty(report)?),
+            reports => {
+                let reports: BTreeMap<&str, &T> = reports.iter().map(|(instance, report)| (instance.unwrap_or_default(), report)).collect();
+                println!("{}", serde_json::to_string_pretty(&reports)?);
+            },
+        }
+    } else {
+        for (instance, report) in reports.iter() {
+            if let Some(instance) = instance {
+                println!("[{}]", instance);
+            }
+            print(report);
+        }
+    }
+    if !failures.is_empty() {
+        return Err(InstanceFailuresError { failures }.into());
+    }
+    Ok(reports.into_iter().map(|(_, report)| report).collect())
+}
+
+impl Cli {
+    /// Installs the logger selected by `--log-format`, `-v` and `-q`.
+    pub fn init_logging(&self) {
+        logging::init(self.log_format, logging::level(self.verbose, self.quiet));
+    }
+
+    /// The timeouts, retries and TLS settings of the requests to Galaxy and to calendar feeds.
+    fn client_settings(&self) -> ClientSettings {
+        ClientSettings {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            request_timeout: Duration::from_secs(self.request_timeout),
+            max_retries: self.retries,
+            requests_per_second: self.rate_limit,
+            burst: self.burst,
+            ca_bundle: self.ca_bundle.clone(),
+            insecure: self.insecure,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the configuration, fetching its calendars with the client settings of the command line.
+    async fn read_config(&self, path: &str) -> Result<ConfigFile> {
+        config::read_config(path, &self.client_settings()).await
+    }
+
+    /// Connects to the Galaxy of the configuration, or to every named instance.
+    async fn training_managers(&self, config: &ConfigFile) -> Result<Vec<TrainingManager>> {
+        let client = self.client_settings();
+        if self.insecure {
+            tracing::warn!("the TLS certificate of Galaxy is not verified");
+        }
+        if config.instances.is_empty() {
+            let galaxy = galaxy::init_galaxy(self.galaxy_url.clone(), self.api_key.clone(), &config.galaxy, client).await?;
+            return Ok(vec![TrainingManager::new(Box::new(galaxy)).with_concurrency(self.concurrency)]);
+        }
+        if self.galaxy_url.is_some() || self.api_key.is_some() {
+            bail!("--galaxy-url and --api-key cannot be used with named instances, set the url and API key of each instance in its [galaxy.<name>] section");
+        }
+        // Connect to every instance first, so a misconfigured one is found before anything changes.
+        let mut training_managers = Vec::new();
+        for (instance, settings) in config.instances.iter() {
+            let galaxy = galaxy::init_instance(instance, settings, client.clone()).await
+                .with_context(|| format!("cannot connect to instance {}", instance))?;
+            training_managers.push(TrainingManager::new(Box::new(galaxy)).with_instance(instance).with_concurrency(self.concurrency));
+        }
+        Ok(training_managers)
+    }
+
+    /// Runs the selected subcommand.
+    pub async fn run(&self) -> Result<ExitStatus> {
+        match &self.command {
+            Command::Apply { config, json } => {
+                let config = self.read_config(config).await?;
+                let training_managers = self.training_managers(&config).await?;
+                run_on_instances(&training_managers, *json, async |training_manager| training_manager.apply_config(&config).await, |plan| print!("{}", plan)).await?;
+                Ok(ExitStatus::Success)
+            },
+            Command::Plan { config, json } => {
+                let config = self.read_config(config).await?;
+                let training_managers = self.training_managers(&config).await?;
+                let plans = run_on_instances(&training_managers, *json, async |training_manager| training_manager.plan(&config).await, |plan| print!("{}", plan)).await?;
+                Ok(if plans.iter().all(Plan::is_empty) { ExitStatus::Success } else { ExitStatus::Drift })
+            },
+            Command::Validate { config, fetch_calendars } => {
+                let source = std::fs::read_to_string(config)?;
+                let diagnostics = validate::validate(&source, Utc::now());
+                for diagnostic in diagnostics.iter() {
+                    eprintln!("{}:{}", config, diagnostic);
+                }
+                let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error).count();
+                if errors > 0 {
+                    eprintln!("{} has {} error(s) and {} warning(s)", config, errors, diagnostics.len() - errors);
+                    return Ok(ExitStatus::Error);
+                }
+                if *fetch_calendars {
+                    // Calendars can only fail at load time. The warnings are already printed.
+                    config::load_config(config, &source, &self.client_settings()).await?;
+                } else {
+                    source.parse::<ConfigFile>()?;
+                }
+                println!("{} is valid", config);
+                Ok(ExitStatus::Success)
+            },
+            Command::Status { config, json } => {
+                let config = self.read_config(config).await?;
+                let training_managers = self.training_managers(&config).await?;
+                run_on_instances(&training_managers, *json, async |training_manager| training_manager.status(&config).await, |statuses| {
+                    for status in statuses.iter() {
+                        print!("{}", status);
+                    }
+                }).await?;
+                Ok(ExitStatus::Success)
+            },
+            Command::Prune { config, dry_run, json } => {
+                let config = self.read_config(config).await?;
+                let training_managers = self.training_managers(&config).await?;
+                let plans = run_on_instances(&training_managers, *json, async |training_manager| {
+                    let plan = training_manager.plan_prune(&config).await?;
+                    if !*dry_run {
+                        training_manager.prune(&plan).await?;
+                    }
+                    Ok(plan)
+                }, |plan| print!("{}", plan)).await?;
+                Ok(if *dry_run && !plans.iter().all(PrunePlan::is_empty) { ExitStatus::Drift } else { ExitStatus::Success })
+            },
+            Command::Export { config, output } => {
+                let config = self.read_config(config).await?;
+                let calendar = calendar::render_calendar(&config, Utc::now());
+                match output {
+                    Some(output) => std::fs::write(output, calendar)?,
+                    None => print!("{}", calendar),
+                }
+                Ok(ExitStatus::Success)
+            },
+            Command::Daemon { config: config_path, resync_interval } => {
+                let config = self.read_config(config_path).await?;
+                let training_managers = self.training_managers(&config).await?;
+                let mut daemon = Daemon::new(training_managers, config_path, config, self.client_settings(), Duration::from_secs(*resync_interval));
+                daemon.run().await?;
+                Ok(ExitStatus::Success)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use crate::manager::GroupFailure;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_hints() {
+        let galaxy_error = |status| galaxy::mock::galaxy_error(status, "/api/groups/1".to_string(), "error".to_string());
+        assert!(hints(&anyhow::anyhow!("not a Galaxy error")).is_empty());
+        assert!(hints(&galaxy_error(reqwest::StatusCode::NOT_FOUND)).is_empty());
+        assert_eq!(hints(&galaxy_error(reqwest::StatusCode::UNAUTHORIZED).context("cannot plan")).len(), 1);
+
+        let failures = vec![
+            GroupFailure { group: "group1".parse().unwrap(), error: galaxy_error(reqwest::StatusCode::FORBIDDEN) },
+            GroupFailure { group: "group2".parse().unwrap(), error: galaxy_error(reqwest::StatusCode::FORBIDDEN) },
+        ];
+        assert_eq!(hints(&GroupUpdatesError { failures }.into()).len(), 1);
+
+        let failures = vec![
+            InstanceFailure { instance: "main".to_string(), error: galaxy_error(reqwest::StatusCode::UNAUTHORIZED) },
+            InstanceFailure { instance: "gpu".to_string(), error: GroupUpdatesError { failures: vec![
+                GroupFailure { group: "group1".parse().unwrap(), error: galaxy_error(reqwest::StatusCode::FORBIDDEN) },
+            ] }.into() },
+        ];
+        assert_eq!(hints(&InstanceFailuresError { failures }.into()).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_instances_refuse_overrides() {
+        let config: ConfigFile = r#"
+        [galaxy.main]
+        url = "https://galaxy.example.org"
+        api_key_command = "pass galaxy/main"
+
+        [groups]
+        team_a = ["alice@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2023-01-01", to = "2023-12-31" }]
+        "#.parse().unwrap();
+        let cli = Cli::try_parse_from(["training-manager", "plan", "config.toml", "--api-key", "key"]).unwrap();
+        let error = cli.training_managers(&config).await.err().unwrap();
+        assert!(error.to_string().starts_with("--galaxy-url and --api-key cannot be used with named instances"));
+    }
+
+    #[test]
+    fn test_parse_global_flags() {
+        let cli = Cli::try_parse_from(["training-manager", "plan", "config.toml", "--json", "--galaxy-url", "http://localhost"]).unwrap();
+        assert_eq!(cli.galaxy_url.as_deref(), Some("http://localhost"));
+        assert!(cli.api_key.is_none());
+        assert_eq!(cli.concurrency, DEFAULT_CONCURRENCY);
+        match cli.command {
+            Command::Plan { config, json } => {
+                assert_eq!(config, "config.toml");
+                assert!(json);
+            },
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_parse_logging_flags() {
+        let cli = Cli::try_parse_from(["training-manager", "daemon", "config.toml", "-vv", "--log-format", "json"]).unwrap();
+        assert_eq!((cli.verbose, cli.quiet, cli.log_format), (2, 0, LogFormat::Json));
+        let cli = Cli::try_parse_from(["training-manager", "apply", "-q", "config.toml"]).unwrap();
+        assert_eq!((cli.verbose, cli.quiet, cli.log_format), (0, 1, LogFormat::Text));
+        assert!(Cli::try_parse_from(["training-manager", "apply", "-v", "-q", "config.toml"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_tls_flags() {
+        let cli = Cli::try_parse_from(["training-manager", "status", "config.toml", "--ca-bundle", "ca.pem", "--insecure"]).unwrap();
+        assert_eq!(cli.ca_bundle, Some(PathBuf::from("ca.pem")));
+        assert!(cli.insecure);
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let cli = Cli::try_parse_from(["training-manager", "apply", "config.toml", "--rate-limit", "2.5", "--burst", "5"]).unwrap();
+        assert_eq!(cli.rate_limit, Some(2.5));
+        assert!(!cli.insecure);
+        assert_eq!(cli.burst, 5);
+        let cli = Cli::try_parse_from(["training-manager", "apply", "config.toml"]).unwrap();
+        assert!(cli.rate_limit.is_none());
+    }
+
+    #[test]
+    fn test_parse_prune() {
+        let cli = Cli::try_parse_from(["training-manager", "prune", "config.toml", "--dry-run"]).unwrap();
+        match cli.command {
+            Command::Prune { config, dry_run, json } => {
+                assert_eq!(config, "config.toml");
+                assert!(dry_run);
+                assert!(!json);
+            },
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_parse_missing_config() {
+        assert!(Cli::try_parse_from(["training-manager", "apply"]).is_err());
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(ExitCode::from(ExitStatus::Success), ExitCode::from(0));
+        assert_eq!(ExitCode::from(ExitStatus::Error), ExitCode::from(1));
+        assert_eq!(ExitCode::from(ExitStatus::Drift), ExitCode::from(2));
+    }
+
+    #[tokio::test]
+    async fn test_export() -> Result<()> {
+        let output = std::env::temp_dir().join(format!("training-manager-export-{}.ics", std::process::id()));
+        let output = output.to_string_lossy().to_string();
+        let cli = Cli::try_parse_from(["training-manager", "export", "./src/sample.toml", "-o", &output])?;
+        assert_eq!(cli.run().await?, ExitStatus::Success);
+        let calendar = std::fs::read_to_string(&output)?;
+        std::fs::remove_file(&output)?;
+        assert!(calendar.contains("SUMMARY:test training (2 members)"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let cli = Cli::try_parse_from(["training-manager", "validate", "./src/sample.toml"]).unwrap();
+        assert_eq!(cli.run().await.unwrap(), ExitStatus::Success);
+        let cli = Cli::try_parse_from(["training-manager", "validate", "not_found.toml"]).unwrap();
+        assert!(cli.run().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_errors() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("training-manager-validate-{}.toml", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(&path, "[groups]\nteam_a = [\"alice@example.com\"]\n\n[schedule]\n")?;
+        let cli = Cli::try_parse_from(["training-manager", "validate", &path])?;
+        let status = cli.run().await?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(status, ExitStatus::Error);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_offline() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("training-manager-validate-offline-{}.toml", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(&path, "[groups]\nteam_a = [\"alice@example.com\"]\n\n[schedule]\nteam_a = { calendar = \"http://127.0.0.1:1/workshops.ics\" }\n")?;
+        let offline = Cli::try_parse_from(["training-manager", "validate", &path])?.run().await;
+        let fetched = Cli::try_parse_from(["training-manager", "validate", "--fetch-calendars", &path])?.run().await;
+        std::fs::remove_file(&path)?;
+        assert_eq!(offline?, ExitStatus::Success);
+        assert!(fetched.is_err());
+        Ok(())
+    }
+}
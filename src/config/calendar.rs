@@ -0,0 +1,620 @@
+//! # Calendar Module
+//!
+//! Imports schedule items from iCalendar (.ics) files and feeds, and exports the
+//! schedule of a configuration as an iCalendar document.
+//!
+//! A group's schedule can point at a calendar, as a path relative to the configuration
+//! file or an http(s) URL. Events whose summary or one of whose categories is `match`,
+//! the group name by default, become schedule items of the group.
+//!
+//! ## Example
+//!
+//! ```toml
+//! [schedule]
+//! team_a = { calendar = "workshops.ics" }
+//! team_b = { calendar = "https://calendar.example.com/training.ics", match = "RNA-seq workshop" }
+//! ```
+//!
+//! Recurring events are supported for daily and weekly `RRULE`s, with `EXDATE` exceptions.
+//!
+//! Exported events cover the effective windows, when the training role is actually
+//! granted, lead and lag included, and are tagged with the group name as category.
+//! Imported back without lead nor lag, they give the same effective windows.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use ical::parser::ical::component::IcalEvent;
+use ical::property::Property;
+use std::fmt::Write;
+use std::path::Path;
+use crate::config::date::{Date, Moment};
+use crate::config::schedule::{Frequency, Recurrence, ScheduleItem};
+use crate::config::{ConfigFile, Grace, TimeRange};
+use crate::galaxy::types::{GroupName, RoleName};
+
+/// Longest line of an iCalendar document, in octets, before it is folded.
+const MAX_LINE_LENGTH: usize = 75;
+
+/// A calendar providing schedule items for a group.
+#[derive(Debug, Clone)]
+pub struct CalendarSource {
+    pub group: GroupName,
+    /// Path, relative to the configuration file, or http(s) URL of the calendar.
+    pub location: String,
+    /// Summary or category of the events to import, ignoring case.
+    pub pattern: String,
+    /// Timezone of events with floating times or dates.
+    pub timezone: Tz,
+    pub grace: Grace,
+    /// Roles granted during the events.
+    pub roles: Vec<RoleName>,
+}
+
+impl CalendarSource {
+    /// Fetches the calendar and converts its matching events to schedule items.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_dir` - The directory a relative path is resolved against.
+    /// * `http` - The client fetching http(s) calendars.
+    pub async fn load(&self, base_dir: &Path, http: &reqwest::Client) -> Result<Vec<ScheduleItem>> {
+        let ics = fetch(&self.location, base_dir, http).await.with_context(|| format!("cannot read calendar {}", self.location))?;
+        let mut schedule_items = parse_calendar(&ics, &self.pattern, self.timezone, self.grace).with_context(|| format!("invalid calendar {}", self.location))?;
+        for schedule_item in schedule_items.iter_mut() {
+            match schedule_item {
+                ScheduleItem::Once(time_range) => time_range.roles = self.roles.clone(),
+                ScheduleItem::Recurring(recurrence) => recurrence.first.roles = self.roles.clone(),
+            }
+        }
+        Ok(schedule_items)
+    }
+}
+
+async fn fetch(location: &str, base_dir: &Path, http: &reqwest::Client) -> Result<String> {
+    let location = match location.strip_prefix("webcal://") {
+        Some(rest) => format!("https://{}", rest),
+        None => location.to_string(),
+    };
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let response = http.get(&location).send().await?.error_for_status()?;
+        return Ok(response.text().await?);
+    }
+    let path = location.strip_prefix("file://").unwrap_or(&location);
+    Ok(std::fs::read_to_string(base_dir.join(path))?)
+}
+
+/// Converts the events of an iCalendar document matching `pattern` to schedule items,
+/// granting no role.
+///
+/// # Arguments
+///
+/// * `ics` - The content of the calendar.
+/// * `pattern` - Summary or category of the events to import, ignoring case.
+/// * `timezone` - Timezone of events with floating times or dates.
+/// * `grace` - Grace periods of the schedule items.
+pub fn parse_calendar(ics: &str, pattern: &str, timezone: Tz, grace: Grace) -> Result<Vec<ScheduleItem>> {
+    let mut items = Vec::new();
+    for calendar in ical::IcalParser::new(ics.as_bytes()) {
+        for event in calendar?.events.iter().filter(|event| event_matches(event, pattern)) {
+            let summary = property(event, "SUMMARY").and_then(|summary| summary.value.clone()).unwrap_or_default();
+            items.push(parse_event(event, timezone, grace).with_context(|| format!("invalid event {}", summary))?);
+        }
+    }
+    Ok(items)
+}
+
+fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+    event.properties.iter().find(|property| property.name == name)
+}
+
+fn parameter<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+    property.params.as_ref()?
+        .iter()
+        .find(|(param, _)| param.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+fn event_matches(event: &IcalEvent, pattern: &str) -> bool {
+    let pattern = pattern.trim();
+    let summary_matches = property(event, "SUMMARY")
+        .and_then(|summary| summary.value.as_deref())
+        .is_some_and(|summary| summary.trim().eq_ignore_ascii_case(pattern));
+    let category_matches = event.properties.iter()
+        .filter(|property| property.name == "CATEGORIES")
+        .filter_map(|categories| categories.value.as_deref())
+        .flat_map(|categories| categories.split(','))
+        .any(|category| category.trim().eq_ignore_ascii_case(pattern));
+    summary_matches || category_matches
+}
+
+/// Parses a `DTSTART`, `DTEND`, `UNTIL` or `EXDATE` value.
+///
+/// Returns the moment and, for local times with a `TZID`, their timezone.
+fn parse_ics_moment(value: &str, tzid: Option<&str>) -> Result<(Moment, Option<Tz>)> {
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d")?;
+        return Ok((Moment::Day(Date(date)), None));
+    }
+    if let Some(value) = value.strip_suffix('Z') {
+        let datetime = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?;
+        return Ok((Moment::Instant(datetime.and_utc().fixed_offset()), None));
+    }
+    let datetime = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?;
+    let timezone = match tzid {
+        Some(tzid) => {
+            let tzid = tzid.trim_start_matches('/');
+            Some(tzid.parse::<Tz>().map_err(|_| anyhow!("unknown timezone {}", tzid))?)
+        },
+        None => None,
+    };
+    Ok((Moment::Local(datetime), timezone))
+}
+
+fn parse_property_moment(property: &Property) -> Result<(Moment, Option<Tz>)> {
+    let value = property.value.as_deref().ok_or(anyhow!("{} has no value", property.name))?;
+    parse_ics_moment(value, parameter(property, "TZID"))
+}
+
+/// Parses an iCalendar duration, such as "PT1H30M" or "P1D".
+fn parse_ics_duration(value: &str) -> Result<TimeDelta> {
+    let invalid = || anyhow!("invalid duration {}", value);
+    let rest = value.trim_start_matches('+').strip_prefix('P').ok_or_else(invalid)?;
+    let mut duration = TimeDelta::zero();
+    let mut number = String::new();
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => continue,
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let value: i64 = number.parse().map_err(|_| invalid())?;
+                duration += match c {
+                    'W' => TimeDelta::weeks(value),
+                    'D' => TimeDelta::days(value),
+                    'H' => TimeDelta::hours(value),
+                    'M' => TimeDelta::minutes(value),
+                    _ => TimeDelta::seconds(value),
+                };
+                number.clear();
+            },
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(duration)
+}
+
+fn parse_event(event: &IcalEvent, default_timezone: Tz, grace: Grace) -> Result<ScheduleItem> {
+    let (from, start_timezone) = parse_property_moment(property(event, "DTSTART").ok_or(anyhow!("DTSTART not found"))?)?;
+    let timezone = start_timezone.unwrap_or(default_timezone);
+
+    let to = match (property(event, "DTEND"), property(event, "DURATION")) {
+        (Some(dtend), _) => {
+            let (to, end_timezone) = parse_property_moment(dtend)?;
+            match to {
+                // All-day events end the day before DTEND.
+                Moment::Day(date) => Moment::Day(Date(date.0.checked_sub_days(Days::new(1)).unwrap_or(date.0).max(from.date(&timezone)))),
+                Moment::Local(datetime) => match end_timezone {
+                    Some(end_timezone) if end_timezone != timezone => Moment::Instant(Moment::Local(datetime).start(&end_timezone).fixed_offset()),
+                    _ => to,
+                },
+                Moment::Instant(_) => to,
+            }
+        },
+        (None, Some(duration)) => {
+            let duration = parse_ics_duration(duration.value.as_deref().unwrap_or_default())?;
+            match from {
+                Moment::Day(date) => Moment::Day(Date(date.0 + Days::new((duration.num_days() - 1).max(0) as u64))),
+                Moment::Local(datetime) => Moment::Local(datetime + duration),
+                Moment::Instant(instant) => Moment::Instant(instant + duration),
+            }
+        },
+        (None, None) => from,
+    };
+
+    let time_range = TimeRange {
+        from,
+        to,
+        timezone,
+        grace,
+        roles: Vec::new(),
+    };
+    match property(event, "RRULE").and_then(|rrule| rrule.value.as_deref()) {
+        Some(rrule) => {
+            let except = event.properties.iter()
+                .filter(|property| property.name == "EXDATE")
+                .flat_map(|exdate| {
+                    let tzid = parameter(exdate, "TZID");
+                    exdate.value.as_deref().unwrap_or_default()
+                        .split(',')
+                        .map(move |value| parse_ics_moment(value.trim(), tzid).map(|(moment, _)| moment.date(&timezone)))
+                })
+                .collect::<Result<Vec<NaiveDate>>>()?;
+            Ok(ScheduleItem::Recurring(parse_rrule(rrule, time_range, except)?))
+        },
+        None => Ok(ScheduleItem::Once(time_range)),
+    }
+}
+
+/// Parses a daily or weekly `RRULE` value, such as "FREQ=WEEKLY;BYDAY=TU,TH;COUNT=28".
+fn parse_rrule(rrule: &str, first: TimeRange, except: Vec<NaiveDate>) -> Result<Recurrence> {
+    let mut frequency = None;
+    let mut interval = 1;
+    let mut weekdays = Vec::new();
+    let mut count = None;
+    let mut until = None;
+    for part in rrule.split(';').filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').ok_or_else(|| anyhow!("invalid RRULE {}", rrule))?;
+        match key {
+            "FREQ" => frequency = Some(match value {
+                "DAILY" => Frequency::Daily,
+                "WEEKLY" => Frequency::Weekly,
+                _ => return Err(anyhow!("unsupported RRULE frequency {}", value)),
+            }),
+            "INTERVAL" => interval = value.parse()?,
+            "COUNT" => count = Some(value.parse()?),
+            "UNTIL" => until = Some(parse_ics_moment(value, None)?.0),
+            "BYDAY" => weekdays = value.split(',').map(parse_ics_weekday).collect::<Result<Vec<Weekday>>>()?,
+            "WKST" => {},
+            _ => return Err(anyhow!("unsupported RRULE part {}", key)),
+        }
+    }
+    let until = until.map(|until: Moment| until.date(&first.timezone));
+    Ok(Recurrence {
+        frequency: frequency.ok_or_else(|| anyhow!("RRULE without FREQ"))?,
+        interval,
+        weekdays,
+        count,
+        until,
+        except,
+        first,
+    })
+}
+
+fn parse_ics_weekday(value: &str) -> Result<Weekday> {
+    match value {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(anyhow!("unsupported BYDAY value {}", value)),
+    }
+}
+
+/// Renders the schedule of a configuration as an iCalendar document.
+///
+/// Each group schedule item becomes one event, named after the group and its number
+/// of members, covering its effective windows rather than the configured ones.
+/// Recurring items keep their rule, with local times in their timezone.
+///
+/// # Arguments
+///
+/// * `config` - The configuration to export.
+/// * `dtstamp` - The creation time of the events.
+///
+/// # Returns
+///
+/// The iCalendar document, with CRLF line endings.
+pub fn render_calendar(config: &ConfigFile, dtstamp: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//training-manager//Training schedule//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:Training schedule".to_string(),
+    ];
+    let mut group_names: Vec<&GroupName> = config.schedule.keys().collect();
+    group_names.sort();
+    for group_name in group_names {
+        let members = config.groups.get(group_name).map_or(0, Vec::len);
+        for (index, schedule_item) in config.schedule[group_name].iter().enumerate() {
+            let time_range = match schedule_item {
+                ScheduleItem::Once(time_range) => time_range,
+                ScheduleItem::Recurring(recurrence) => &recurrence.first,
+            };
+            lines.push("BEGIN:VEVENT".to_string());
+            lines.push(format!("UID:{}-{}@training-manager", escape_text(&group_name.to_string()), index));
+            lines.push(format!("DTSTAMP:{}", dtstamp.format("%Y%m%dT%H%M%SZ")));
+            lines.push(format!("SUMMARY:{}", escape_text(&format!("{} training ({} member{})", group_name, members, if members == 1 { "" } else { "s" }))));
+            lines.push(format!("CATEGORIES:{}", escape_text(&group_name.to_string())));
+            lines.push(format!("DTSTART{}", format_ics_datetime(time_range.effective_start(), time_range.timezone)));
+            lines.push(format!("DTEND{}", format_ics_datetime(time_range.effective_end(), time_range.timezone)));
+            if let ScheduleItem::Recurring(recurrence) = schedule_item {
+                lines.push(format!("RRULE:{}", format_rrule(recurrence)));
+                for day in recurrence.except.iter() {
+                    lines.push(format!("EXDATE{}", format_ics_datetime(occurrence_on(recurrence, *day).effective_start(), time_range.timezone)));
+                }
+            }
+            lines.push("END:VEVENT".to_string());
+        }
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut calendar = String::new();
+    for line in lines.iter() {
+        calendar.push_str(&fold_line(line));
+        calendar.push_str("\r\n");
+    }
+    calendar
+}
+
+/// Formats an instant as the parameters and value of a date-time property: in UTC
+/// for UTC ranges, in local time with a `TZID` otherwise.
+fn format_ics_datetime(instant: DateTime<Utc>, timezone: Tz) -> String {
+    if timezone == Tz::UTC {
+        return format!(":{}", instant.format("%Y%m%dT%H%M%SZ"));
+    }
+    format!(";TZID={}:{}", timezone.name(), instant.with_timezone(&timezone).format("%Y%m%dT%H%M%S"))
+}
+
+/// Returns the occurrence of a recurrence starting on `day`, whether or not the rule allows it.
+fn occurrence_on(recurrence: &Recurrence, day: NaiveDate) -> TimeRange {
+    let first = &recurrence.first;
+    let offset = (day - first.from.date(&first.timezone)).num_days().max(0) as u64;
+    TimeRange {
+        from: first.from.add_days(offset, &first.timezone),
+        to: first.to.add_days(offset, &first.timezone),
+        timezone: first.timezone,
+        grace: first.grace,
+        roles: first.roles.clone(),
+    }
+}
+
+fn format_rrule(recurrence: &Recurrence) -> String {
+    let mut rrule = match recurrence.frequency {
+        Frequency::Daily => "FREQ=DAILY".to_string(),
+        Frequency::Weekly => "FREQ=WEEKLY;WKST=MO".to_string(),
+    };
+    if recurrence.interval > 1 {
+        let _ = write!(rrule, ";INTERVAL={}", recurrence.interval);
+    }
+    if !recurrence.weekdays.is_empty() {
+        let weekdays: Vec<&str> = recurrence.weekdays.iter().map(|weekday| format_ics_weekday(*weekday)).collect();
+        let _ = write!(rrule, ";BYDAY={}", weekdays.join(","));
+    }
+    if let Some(count) = recurrence.count {
+        let _ = write!(rrule, ";COUNT={}", count);
+    }
+    if let Some(until) = recurrence.until {
+        // The last occurrence starts on `until`, so its effective start is before the end of that day minus the lead.
+        let until = Moment::Day(Date(until)).end(&recurrence.first.timezone) - recurrence.first.grace.lead - TimeDelta::seconds(1);
+        let _ = write!(rrule, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"));
+    }
+    rrule
+}
+
+fn format_ics_weekday(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Escapes the characters with a special meaning in iCalendar text values.
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Splits a line longer than 75 octets into continuation lines starting with a space.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::schedule::Schedule;
+    use crate::galaxy::ClientSettings;
+
+    const FIXTURE: &str = "./src/fixtures/workshops.ics";
+
+    fn instant(s: &str) -> chrono::DateTime<chrono::Utc> {
+        s.parse().unwrap()
+    }
+
+    fn load(pattern: &str) -> Vec<ScheduleItem> {
+        let ics = std::fs::read_to_string(FIXTURE).unwrap();
+        parse_calendar(&ics, pattern, "America/Montreal".parse().unwrap(), Grace::default()).unwrap()
+    }
+
+    #[test]
+    fn test_match_by_summary() {
+        let items = load("rna-seq workshop");
+        assert_eq!(items.len(), 2);
+        // Timed event with a TZID.
+        assert!(items[0].contains(&instant("2024-05-01T13:30:00Z")));
+        assert!(!items[0].contains(&instant("2024-05-01T21:00:00Z")));
+        // All-day event over two days, DTEND is exclusive.
+        assert!(items[1].contains(&instant("2024-06-11T12:00:00Z")));
+        assert!(!items[1].contains(&instant("2024-06-12T12:00:00Z")));
+    }
+
+    #[test]
+    fn test_match_by_category() {
+        let items = load("GPU");
+        assert_eq!(items.len(), 2);
+        // UTC event with a duration.
+        assert!(items[0].contains(&instant("2024-07-02T10:30:00Z")));
+        assert!(!items[0].contains(&instant("2024-07-02T11:00:00Z")));
+    }
+
+    #[test]
+    fn test_recurring_event() {
+        let items = load("weekly course");
+        assert_eq!(items.len(), 1);
+        assert!(matches!(items[0], ScheduleItem::Recurring(_)));
+        assert!(items[0].contains(&instant("2024-09-05T18:00:00Z")));
+        assert!(!items[0].contains(&instant("2024-09-10T18:00:00Z")));
+        assert!(!items[0].contains(&instant("2024-09-26T18:00:00Z")));
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert!(load("unknown").is_empty());
+    }
+
+    #[test]
+    fn test_parse_ics_duration() {
+        assert_eq!(parse_ics_duration("PT1H30M").unwrap(), TimeDelta::minutes(90));
+        assert_eq!(parse_ics_duration("P1W").unwrap(), TimeDelta::days(7));
+        assert!(parse_ics_duration("1H").is_err());
+    }
+
+    #[test]
+    fn test_render_calendar() -> Result<()> {
+        let config: ConfigFile = r#"
+        [groups]
+        course = ["alice@example.com", "bob@example.com"]
+        workshop = ["carol@example.com"]
+
+        [schedule]
+        course = { timezone = "America/Montreal", lead = "1h", windows = [
+            { from = "2024-09-05T14:00", to = "2024-09-05T16:00", repeat = { frequency = "weekly", weekdays = ["thu"], count = 3, except = ["2024-09-19"] } },
+        ] }
+        workshop = [{ from = "2024-05-01", to = "2024-05-02" }]
+        "#.parse()?;
+        let calendar = render_calendar(&config, instant("2024-01-01T00:00:00Z"));
+
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.contains("SUMMARY:course training (2 members)\r\n"));
+        assert!(calendar.contains("SUMMARY:workshop training (1 member)\r\n"));
+        assert!(calendar.contains("DTSTART;TZID=America/Montreal:20240905T130000\r\n"));
+        assert!(calendar.contains("RRULE:FREQ=WEEKLY;WKST=MO;BYDAY=TH;COUNT=3\r\n"));
+        assert!(calendar.contains("EXDATE;TZID=America/Montreal:20240919T130000\r\n"));
+        assert!(calendar.contains("DTSTART:20240501T000000Z\r\n"));
+        assert!(calendar.contains("DTEND:20240503T000000Z\r\n"));
+
+        // Events can be imported back by category.
+        let course = parse_calendar(&calendar, "course", Tz::UTC, Grace::default())?;
+        assert_eq!(course.len(), 1);
+        assert!(course[0].contains(&instant("2024-09-12T17:30:00Z")));
+        assert!(!course[0].contains(&instant("2024-09-19T17:30:00Z")));
+        assert!(!course[0].contains(&instant("2024-09-26T17:30:00Z")));
+        Ok(())
+    }
+
+    /// The effective windows of a schedule item, as start and end instants.
+    fn effective_windows(schedule_item: &ScheduleItem) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        match schedule_item {
+            ScheduleItem::Once(time_range) => vec![(time_range.effective_start(), time_range.effective_end())],
+            ScheduleItem::Recurring(recurrence) => recurrence.occurrences().map(|occurrence| (occurrence.effective_start(), occurrence.effective_end())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_render_calendar_round_trip() -> Result<()> {
+        let config: ConfigFile = r#"
+        [groups]
+        course = ["alice@example.com"]
+        workshop = ["carol@example.com"]
+
+        [schedule]
+        course = { timezone = "America/Montreal", lead = "1h", lag = "30m", windows = [
+            { from = "2024-09-05T14:00", to = "2024-09-05T16:00", repeat = { frequency = "weekly", weekdays = ["tue", "thu"], count = 6, except = ["2024-09-12"] } },
+        ] }
+        workshop = { lead = "1d", windows = [{ from = "2024-05-01", to = "2024-05-02" }] }
+        "#.parse()?;
+        let calendar = render_calendar(&config, instant("2024-01-01T00:00:00Z"));
+
+        // Imported back without grace, the events are the effective windows of the configuration.
+        for group_name in ["course", "workshop"] {
+            let imported = parse_calendar(&calendar, group_name, Tz::UTC, Grace::default())?;
+            let configured = &config.schedule[&group_name.parse()?];
+            assert_eq!(imported.len(), configured.len());
+            for (imported, configured) in imported.iter().zip(configured.iter()) {
+                assert_eq!(effective_windows(imported), effective_windows(configured));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fold_line() {
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        let folded = fold_line(&line);
+        assert!(folded.split("\r\n").all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert_eq!(folded.replace("\r\n ", ""), line);
+        assert_eq!(escape_text("a,b;c\\d\ne"), "a\\,b\\;c\\\\d\\ne");
+    }
+
+    #[tokio::test]
+    async fn test_load_from_url() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server.mock("GET", "/workshops.ics")
+            .with_status(200)
+            .with_body(std::fs::read_to_string(FIXTURE).unwrap())
+            .create_async()
+            .await;
+        let source = CalendarSource {
+            group: "team_a".parse().unwrap(),
+            location: format!("{}/workshops.ics", server.url()),
+            pattern: "GPU".to_string(),
+            timezone: Tz::UTC,
+            grace: Grace::default(),
+            roles: Vec::new(),
+        };
+        let items = source.load(Path::new("."), &reqwest::Client::new()).await.unwrap();
+        assert_eq!(items.len(), 2);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_load_timeout() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server.mock("GET", "/workshops.ics")
+            .with_status(200)
+            .with_chunked_body(|writer| {
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                writer.write_all(std::fs::read_to_string(FIXTURE).unwrap().as_bytes())
+            })
+            .create_async()
+            .await;
+        let source = CalendarSource {
+            group: "team_a".parse().unwrap(),
+            location: format!("{}/workshops.ics", server.url()),
+            pattern: "GPU".to_string(),
+            timezone: Tz::UTC,
+            grace: Grace::default(),
+            roles: Vec::new(),
+        };
+        let settings = ClientSettings { request_timeout: std::time::Duration::from_millis(200), ..Default::default() };
+        let http = crate::galaxy::http_client(&settings).unwrap();
+        assert!(source.load(Path::new("."), &http).await.is_err());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_load_from_file() {
+        let source = CalendarSource {
+            group: "team_a".parse().unwrap(),
+            location: "workshops.ics".to_string(),
+            pattern: "GPU".to_string(),
+            timezone: Tz::UTC,
+            grace: Grace::default(),
+            roles: Vec::new(),
+        };
+        assert_eq!(source.load(Path::new("./src/fixtures"), &reqwest::Client::new()).await.unwrap().len(), 2);
+        assert!(source.load(Path::new("./not_found"), &reqwest::Client::new()).await.is_err());
+    }
+}
@@ -1,5 +1,7 @@
+use std::fmt::Display;
 use std::str::FromStr;
-use chrono::{NaiveDate, Utc};
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
 
 /// Represents a date using a `NaiveDate` from the `chrono` crate.
 ///
@@ -12,39 +14,9 @@ use chrono::{NaiveDate, Utc};
 /// let date = Date::from_str("2023-05-15").unwrap();
 /// assert_eq!(date.0.to_string(), "2023-05-15");
 /// ```
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Date(pub NaiveDate);
 
-impl Date {
-    /// Returns a `Date` instance representing the current date.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use config::Date;
-    /// use chrono::Utc;
-    ///
-    /// let now = Date::now();
-    /// assert_eq!(now.0.to_string(), Utc::now().date_naive().to_string());
-    /// ```
-    pub fn now() -> Date {
-        Date(Utc::now().date_naive())
-    }
-    /// Returns a `Date` instance representing the given year, month, and day.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use config::Date;
-    ///
-    /// let date = Date::from_ymd(2023, 5, 15);
-    /// assert_eq!(date.0.to_string(), "2023-05-15");
-    /// ```
-    pub fn from_ymd(year: i32, month: u32, day: u32) -> Date {
-        Date(NaiveDate::from_ymd_opt(year, month, day).unwrap())
-    }
-}
-
 impl FromStr for Date {
     type Err = anyhow::Error;
 
@@ -67,21 +39,158 @@ impl FromStr for Date {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Formats for wall-clock datetimes without an offset, tried in order.
+const LOCAL_DATETIME_FORMATS: [&str; 4] = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"];
 
-    #[test]
-    fn test_date_now() {
-        let now = Date::now();
-        assert_eq!(now.0, Utc::now().date_naive());
+/// A bound of a schedule item.
+///
+/// # Examples
+///
+/// ```
+/// use config::date::Moment;
+///
+/// assert!(matches!("2023-05-15".parse::<Moment>().unwrap(), Moment::Day(_)));
+/// assert!(matches!("2023-05-15T09:00:00".parse::<Moment>().unwrap(), Moment::Local(_)));
+/// assert!(matches!("2023-05-15T09:00:00-04:00".parse::<Moment>().unwrap(), Moment::Instant(_)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moment {
+    /// A whole day in the schedule's timezone.
+    Day(Date),
+    /// A wall-clock datetime in the schedule's timezone.
+    Local(NaiveDateTime),
+    /// An absolute instant, as given by an RFC 3339 datetime.
+    Instant(DateTime<FixedOffset>),
+}
+
+impl Moment {
+    /// Returns the instant a range starting at this moment starts.
+    ///
+    /// A day starts at midnight in `timezone`.
+    pub fn start(&self, timezone: &Tz) -> DateTime<Utc> {
+        match self {
+            Moment::Day(date) => resolve_local(timezone, &date.0.and_time(NaiveTime::MIN)),
+            Moment::Local(datetime) => resolve_local(timezone, datetime),
+            Moment::Instant(instant) => instant.with_timezone(&Utc),
+        }
     }
 
-    #[test]
-    fn test_date_from_ymd() {
-        let date = Date::from_ymd(2023, 5, 15);
-        assert_eq!(date.0, NaiveDate::from_ymd_opt(2023, 5, 15).unwrap());
+    /// Returns the instant a range ending at this moment ends.
+    ///
+    /// A day ends at midnight in `timezone` on the following day, so the whole day is included.
+    pub fn end(&self, timezone: &Tz) -> DateTime<Utc> {
+        match self {
+            Moment::Day(date) => {
+                let day_after = date.0.checked_add_days(Days::new(1)).unwrap_or(date.0);
+                resolve_local(timezone, &day_after.and_time(NaiveTime::MIN))
+            },
+            Moment::Local(datetime) => resolve_local(timezone, datetime),
+            Moment::Instant(instant) => instant.with_timezone(&Utc),
+        }
+    }
+
+    /// Returns the day of the moment in `timezone`.
+    pub fn date(&self, timezone: &Tz) -> NaiveDate {
+        match self {
+            Moment::Day(date) => date.0,
+            Moment::Local(datetime) => datetime.date(),
+            Moment::Instant(instant) => instant.with_timezone(timezone).date_naive(),
+        }
+    }
+
+    /// Returns the moment with the same wall-clock time in `timezone`, `days` days later.
+    pub fn add_days(&self, days: u64, timezone: &Tz) -> Moment {
+        let days = Days::new(days);
+        match self {
+            Moment::Day(date) => Moment::Day(Date(date.0 + days)),
+            Moment::Local(datetime) => Moment::Local(*datetime + days),
+            Moment::Instant(instant) => Moment::Local(instant.with_timezone(timezone).naive_local() + days),
+        }
     }
+}
+
+/// Converts a wall-clock datetime to an instant.
+///
+/// Ambiguous datetimes, repeated when clocks go back, resolve to the earliest instant.
+/// Datetimes skipped when clocks go forward resolve to the first instant after the gap.
+fn resolve_local(timezone: &Tz, datetime: &NaiveDateTime) -> DateTime<Utc> {
+    let mut candidate = *datetime;
+    for _ in 0..4 {
+        if let Some(instant) = timezone.from_local_datetime(&candidate).earliest() {
+            return instant.with_timezone(&Utc);
+        }
+        candidate += TimeDelta::minutes(30);
+    }
+    datetime.and_utc()
+}
+
+impl FromStr for Moment {
+    type Err = anyhow::Error;
+
+    /// Parses a date ("YYYY-MM-DD"), an RFC 3339 datetime or a local datetime ("YYYY-MM-DDTHH:MM[:SS]").
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(date) = s.parse::<Date>() {
+            return Ok(Moment::Day(date));
+        }
+        if let Ok(instant) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Moment::Instant(instant));
+        }
+        LOCAL_DATETIME_FORMATS.iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+            .map(Moment::Local)
+            .ok_or_else(|| anyhow::anyhow!("invalid date or datetime: {}", s))
+    }
+}
+
+impl Display for Moment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Moment::Day(date) => write!(f, "{}", date.0),
+            Moment::Local(datetime) => write!(f, "{}", datetime.format("%Y-%m-%dT%H:%M:%S")),
+            Moment::Instant(instant) => write!(f, "{}", instant.to_rfc3339()),
+        }
+    }
+}
+
+/// Parses a duration such as "30m", "2h" or "1d12h". Units are `d`, `h`, `m` and `s`.
+///
+/// # Examples
+///
+/// ```
+/// use config::date::parse_duration;
+/// use chrono::TimeDelta;
+///
+/// assert_eq!(parse_duration("1h30m").unwrap(), TimeDelta::minutes(90));
+/// assert!(parse_duration("90").is_err());
+/// ```
+pub fn parse_duration(s: &str) -> anyhow::Result<TimeDelta> {
+    let mut duration = TimeDelta::zero();
+    let mut number = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: i64 = number.parse().map_err(|_| anyhow::anyhow!("invalid duration {}", s))?;
+        let unit = match c {
+            'd' => TimeDelta::try_days(value),
+            'h' => TimeDelta::try_hours(value),
+            'm' => TimeDelta::try_minutes(value),
+            's' => TimeDelta::try_seconds(value),
+            _ => None,
+        };
+        duration += unit.ok_or_else(|| anyhow::anyhow!("invalid duration {}", s))?;
+        number.clear();
+    }
+    if !number.is_empty() || s.trim().is_empty() {
+        return Err(anyhow::anyhow!("invalid duration {}, expected a number followed by d, h, m or s", s));
+    }
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
     fn test_date_from_str_valid() {
@@ -100,4 +209,62 @@ mod tests {
         let date2 = Date::from_str("2023-05-16").unwrap();
         assert!(date1 < date2);
     }
+
+    #[test]
+    fn test_moment_from_str() {
+        assert_eq!("2023-05-15".parse::<Moment>().unwrap(), Moment::Day(Date(NaiveDate::from_ymd_opt(2023, 5, 15).unwrap())));
+        assert!(matches!("2023-05-15T09:30".parse::<Moment>().unwrap(), Moment::Local(_)));
+        assert!(matches!("2023-05-15 09:30:00".parse::<Moment>().unwrap(), Moment::Local(_)));
+        assert!(matches!("2023-05-15T09:30:00Z".parse::<Moment>().unwrap(), Moment::Instant(_)));
+        assert!("2023-05-15T25:00".parse::<Moment>().is_err());
+    }
+
+    #[test]
+    fn test_moment_day_in_timezone() {
+        let montreal: Tz = "America/Montreal".parse().unwrap();
+        let day = Moment::Day(Date(NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()));
+        assert_eq!(day.start(&montreal).to_rfc3339(), "2024-05-01T04:00:00+00:00");
+        assert_eq!(day.end(&montreal).to_rfc3339(), "2024-05-02T04:00:00+00:00");
+        assert_eq!(day.start(&Tz::UTC).to_rfc3339(), "2024-05-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_moment_local_and_instant() {
+        let montreal: Tz = "America/Montreal".parse().unwrap();
+        let local = "2024-05-01T09:00".parse::<Moment>().unwrap();
+        assert_eq!(local.start(&montreal).to_rfc3339(), "2024-05-01T13:00:00+00:00");
+        let instant = "2024-05-01T09:00:00+02:00".parse::<Moment>().unwrap();
+        assert_eq!(instant.start(&montreal).to_rfc3339(), "2024-05-01T07:00:00+00:00");
+        assert_eq!(instant.end(&montreal), instant.start(&montreal));
+    }
+
+    #[test]
+    fn test_moment_daylight_saving_gap() {
+        let montreal: Tz = "America/Montreal".parse().unwrap();
+        // Clocks jump from 02:00 to 03:00 on 2024-03-10.
+        let skipped = "2024-03-10T02:30".parse::<Moment>().unwrap();
+        assert_eq!(skipped.start(&montreal).to_rfc3339(), "2024-03-10T07:00:00+00:00");
+    }
+
+    #[test]
+    fn test_moment_add_days() {
+        let montreal: Tz = "America/Montreal".parse().unwrap();
+        let day = "2024-03-09".parse::<Moment>().unwrap();
+        assert_eq!(day.add_days(2, &montreal), "2024-03-11".parse::<Moment>().unwrap());
+        // The wall-clock time is kept across the change to daylight saving time.
+        let instant = "2024-03-09T09:00:00-05:00".parse::<Moment>().unwrap();
+        assert_eq!(instant.add_days(2, &montreal).start(&montreal).to_rfc3339(), "2024-03-11T13:00:00+00:00");
+        assert_eq!(instant.date(&montreal), NaiveDate::from_ymd_opt(2024, 3, 9).unwrap());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m").unwrap(), TimeDelta::minutes(30));
+        assert_eq!(parse_duration("1d12h").unwrap(), TimeDelta::hours(36));
+        assert_eq!(parse_duration("45s").unwrap(), TimeDelta::seconds(45));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("2w").is_err());
+        assert!(parse_duration("h").is_err());
+    }
 }
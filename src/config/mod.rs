@@ -30,47 +30,122 @@
 //! assert_eq!(config.schedule.len(), 2);
 //! ```
 
+pub mod calendar;
 pub mod date;
+pub mod schedule;
+pub mod validate;
 
-use anyhow::Result;
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use crate::galaxy::types::{Email, GroupName};
+use crate::galaxy::{self, ApiKeySource, ClientSettings, GalaxySettings};
+use crate::galaxy::types::{Email, GroupName, RoleName};
 
-use date::Date;
+use calendar::CalendarSource;
+use date::Moment;
+use validate::{Severity, ValidationError};
+use schedule::{Frequency, Recurrence, Schedule, ScheduleItem};
 
-/// Represents a time range with a start and end date.
-#[derive(Debug)]
+/// Name of the role granted by windows that do not say which roles they grant.
+pub const DEFAULT_ROLE: &str = "training";
+
+/// Grace periods widening a time range, so jobs submitted just before a
+/// training starts or still queued right after it ends get the training role.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Grace {
+    /// How long before the start of the range it takes effect.
+    pub lead: TimeDelta,
+    /// How long after the end of the range it stays in effect.
+    pub lag: TimeDelta,
+}
+
+/// Represents a time range between two moments, evaluated in a timezone.
+///
+/// Plain dates are whole days: a range from `2023-01-01` to `2023-01-31` covers
+/// January entirely, from midnight on the 1st to midnight on February 1st in `timezone`.
+///
+/// `from` and `to` are the nominal window. The effective window, used to decide
+/// whether a group is in training, is widened by `grace`. During the effective
+/// window, the group is granted `roles`.
+#[derive(Debug, Clone)]
 pub struct TimeRange {
-    pub from: Date,
-    pub to: Date,
+    pub from: Moment,
+    pub to: Moment,
+    pub timezone: Tz,
+    pub grace: Grace,
+    pub roles: Vec<RoleName>,
 }
 
 impl TimeRange {
-    /// Checks if a given date is within the time range.
-    ///
-    /// # Arguments
-    ///
-    /// * `date` - The date to check.
-    ///
-    /// # Returns
-    ///
-    /// `true` if the date is within the range, `false` otherwise.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// use your_crate_name::config::{TimeRange, Date};
-    ///
-    /// let range = TimeRange {
-    ///     from: Date(2023, 1, 1),
-    ///     to: Date(2023, 12, 31),
-    /// };
-    /// assert!(range.contains(&Date(2023, 6, 15)));
-    /// assert!(!range.contains(&Date(2024, 1, 1)));
-    /// ```
-    pub fn contains(&self, date: &Date) -> bool {
-        date.0 >= self.from.0 && date.0 <= self.to.0
+    /// Returns the instant the nominal window starts.
+    pub fn start(&self) -> DateTime<Utc> {
+        self.from.start(&self.timezone)
+    }
+
+    /// Returns the instant the nominal window ends, excluded from the window.
+    pub fn end(&self) -> DateTime<Utc> {
+        self.to.end(&self.timezone)
+    }
+
+    /// Returns the instant the effective window starts, `grace.lead` before the nominal start.
+    pub fn effective_start(&self) -> DateTime<Utc> {
+        self.start() - self.grace.lead
+    }
+
+    /// Returns the instant the effective window ends, `grace.lag` after the nominal end.
+    pub fn effective_end(&self) -> DateTime<Utc> {
+        self.end() + self.grace.lag
+    }
+}
+
+/// What to do with configured emails that have no Galaxy account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissingUserPolicy {
+    /// Leave the user out of its groups and warn.
+    Skip,
+    /// Refuse to plan, so nothing is changed in Galaxy.
+    #[default]
+    Fail,
+    /// Create the user and send it a password reset email.
+    Create,
+}
+
+impl FromStr for MissingUserPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(MissingUserPolicy::Skip),
+            "fail" => Ok(MissingUserPolicy::Fail),
+            "create" => Ok(MissingUserPolicy::Create),
+            _ => Err(anyhow::anyhow!("invalid missing_users policy {}, expected skip, fail or create", s)),
+        }
+    }
+}
+
+/// How group members and roles that the configuration does not mention are treated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReconcileMode {
+    /// Make the members and roles of each group exactly those of the configuration.
+    #[default]
+    Replace,
+    /// Only add or remove the declared roles and the configured members, and keep
+    /// those added by hand in Galaxy.
+    Preserve,
+}
+
+impl FromStr for ReconcileMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(ReconcileMode::Replace),
+            "preserve" => Ok(ReconcileMode::Preserve),
+            _ => Err(anyhow::anyhow!("invalid reconcile mode {}, expected replace or preserve", s)),
+        }
     }
 }
 
@@ -79,8 +154,78 @@ impl TimeRange {
 pub struct ConfigFile {
     /// Mapping of group names to lists of email addresses.
     pub groups: HashMap<GroupName, Vec<Email>>,
-    /// Mapping of group names to lists of time ranges for scheduling.
-    pub schedule: HashMap<GroupName, Vec<TimeRange>>,
+    /// Mapping of group names to lists of schedule items.
+    pub schedule: HashMap<GroupName, Vec<ScheduleItem>>,
+    /// Calendars whose events are added to the schedule by `load_calendars`.
+    pub calendars: Vec<CalendarSource>,
+    /// What to do with configured emails that have no Galaxy account.
+    pub missing_users: MissingUserPolicy,
+    /// How members and roles the configuration does not mention are treated.
+    pub reconcile: ReconcileMode,
+    /// Roles managed by the configuration, with their description.
+    pub roles: BTreeMap<RoleName, String>,
+    /// File recording the groups and roles created by the training manager.
+    /// `read_config` defaults it to the configuration path with a `.state.json` extension.
+    pub state: Option<PathBuf>,
+    /// Galaxy URL and API key, used when neither the command line nor the environment set them.
+    pub galaxy: GalaxySettings,
+    /// Named Galaxy instances, each with its own URL and API key. Empty when the
+    /// configuration manages a single Galaxy.
+    pub instances: BTreeMap<String, GalaxySettings>,
+    /// Named instances a group is reconciled on, for the groups that do not go to every instance.
+    pub group_instances: HashMap<GroupName, BTreeSet<String>>,
+}
+
+impl ConfigFile {
+    /// Fetches the calendars of the configuration and adds their events to the schedule.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_dir` - The directory relative calendar paths are resolved against.
+    /// * `http` - The client fetching http(s) calendars.
+    pub async fn load_calendars(&mut self, base_dir: &Path, http: &reqwest::Client) -> Result<()> {
+        for calendar in self.calendars.iter() {
+            let schedule_items = calendar.load(base_dir, http).await?;
+            self.schedule.entry(calendar.group.clone()).or_default().extend(schedule_items);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if `group` is reconciled on `instance`, the name of a named
+    /// instance or `None` for a single Galaxy.
+    pub fn applies_to(&self, group: &GroupName, instance: Option<&str>) -> bool {
+        match (instance, self.group_instances.get(group)) {
+            (Some(instance), Some(instances)) => instances.contains(instance),
+            _ => true,
+        }
+    }
+
+    /// Returns the state file of `instance`: the state file of the configuration, with
+    /// the name of the instance inserted before its extension for a named instance.
+    pub fn state_for(&self, instance: Option<&str>) -> Option<PathBuf> {
+        let state = self.state.as_ref()?;
+        let Some(instance) = instance else {
+            return Some(state.clone());
+        };
+        let mut file_name = state.file_stem().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{}", instance));
+        if let Some(extension) = state.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        Some(state.with_file_name(file_name))
+    }
+
+    /// Returns the first instant strictly after `after` at which any schedule item starts or ends.
+    ///
+    /// The training role of some group may change at that instant, so it is when a
+    /// long-running process should reconcile next. Returns `None` once every range is over.
+    pub fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule.values()
+            .flatten()
+            .filter_map(|schedule_item| schedule_item.next_transition(after))
+            .min()
+    }
 }
 
 impl FromStr for ConfigFile {
@@ -120,11 +265,41 @@ impl FromStr for ConfigFile {
         let config: toml::Table = toml::from_str(s)?;
         let groups_table = config.get("groups").ok_or(anyhow::anyhow!("groups not found"))?.as_table().ok_or(anyhow::anyhow!("groups not found"))?;
         let schedule_table = config.get("schedule").ok_or(anyhow::anyhow!("schedule not found"))?.as_table().ok_or(anyhow::anyhow!("schedule not found"))?;
+        let defaults = parse_schedule_defaults(&config, &ScheduleDefaults::default())?;
         let groups = parse_groups(groups_table)?;
-        let schedule = parse_schedule(schedule_table)?;
+        let (schedule, calendars) = parse_schedule(schedule_table, &defaults)?;
+        let roles = match config.get("roles") {
+            Some(roles) => parse_roles(roles.as_table().ok_or(anyhow::anyhow!("roles must be a table"))?)?,
+            None => BTreeMap::from([(DEFAULT_ROLE.parse()?, String::new())]),
+        };
+        let missing_users = match config.get("missing_users") {
+            Some(missing_users) => missing_users.as_str().ok_or(anyhow::anyhow!("missing_users must be a string"))?.parse()?,
+            None => MissingUserPolicy::default(),
+        };
+        let reconcile = match config.get("reconcile") {
+            Some(reconcile) => reconcile.as_str().ok_or(anyhow::anyhow!("reconcile must be a string"))?.parse()?,
+            None => ReconcileMode::default(),
+        };
+        let state = match config.get("state") {
+            Some(state) => Some(PathBuf::from(state.as_str().ok_or(anyhow::anyhow!("state must be a path"))?)),
+            None => None,
+        };
+        let (galaxy, instances) = match config.get("galaxy") {
+            Some(galaxy) => parse_galaxy(galaxy.as_table().ok_or(anyhow::anyhow!("galaxy must be a table"))?)?,
+            None => (GalaxySettings::default(), BTreeMap::new()),
+        };
+        let group_instances = parse_group_instances(schedule_table, &instances)?;
         Ok(ConfigFile {
             groups,
             schedule,
+            calendars,
+            missing_users,
+            reconcile,
+            roles,
+            state,
+            galaxy,
+            instances,
+            group_instances,
         })
     }
 }
@@ -152,56 +327,426 @@ fn parse_groups(groups: &toml::Table) -> Result<HashMap<GroupName, Vec<Email>>>
     Ok(groups_map)
 }
 
+/// Parses the roles section of the configuration.
+///
+/// Each role is declared with its description, either as a string or as a
+/// table with a `description` key.
+///
+/// # Arguments
+///
+/// * `roles` - The TOML table declaring the roles.
+///
+/// # Returns
+///
+/// A `Result` containing a `BTreeMap` of role names to descriptions.
+fn parse_roles(roles: &toml::Table) -> Result<BTreeMap<RoleName, String>> {
+    let mut roles_map = BTreeMap::new();
+    for (role_name, role) in roles.iter() {
+        let description = match role {
+            toml::Value::String(description) => description.clone(),
+            toml::Value::Table(role) => match role.get("description") {
+                Some(description) => description.as_str().ok_or(anyhow::anyhow!("description of role {} must be a string", role_name))?.to_string(),
+                None => String::new(),
+            },
+            _ => return Err(anyhow::anyhow!("role {} must be a description or a table", role_name)),
+        };
+        roles_map.insert(role_name.parse()?, description);
+    }
+    Ok(roles_map)
+}
+
+/// Parses the galaxy section of the configuration.
+///
+/// The section either sets the `url` and API key of a single Galaxy, or declares
+/// named instances, as tables with the same keys, when any of its values is a table.
+///
+/// # Arguments
+///
+/// * `galaxy` - The TOML table of the galaxy section.
+///
+/// # Returns
+///
+/// A `Result` containing the settings of the single Galaxy, the default ones with
+/// named instances, and the named instances.
+fn parse_galaxy(galaxy: &toml::Table) -> Result<(GalaxySettings, BTreeMap<String, GalaxySettings>)> {
+    if !galaxy.values().any(toml::Value::is_table) {
+        return Ok((parse_galaxy_settings(galaxy)?, BTreeMap::new()));
+    }
+    let mut instances = BTreeMap::new();
+    for (name, instance) in galaxy.iter() {
+        let instance = instance.as_table().ok_or(anyhow::anyhow!("instance {} must be a table", name))?;
+        instances.insert(parse_instance_name(name)?, parse_instance(name, instance)?);
+    }
+    Ok((GalaxySettings::default(), instances))
+}
+
+/// Checks the name of an instance, which is used in the name of its state file.
+pub(crate) fn parse_instance_name(name: &str) -> Result<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow::anyhow!("invalid instance name {}, expected letters, digits, - and _", name));
+    }
+    Ok(name.to_string())
+}
+
+/// Parses a named instance, which must set both its `url` and its API key.
+pub(crate) fn parse_instance(name: &str, instance: &toml::Table) -> Result<GalaxySettings> {
+    let settings = parse_galaxy_settings(instance)?;
+    if settings.url.is_none() {
+        return Err(anyhow::anyhow!("instance {} has no url", name));
+    }
+    if settings.api_key.is_none() {
+        return Err(anyhow::anyhow!("instance {} has no API key", name));
+    }
+    Ok(settings)
+}
+
+/// Parses the `instances` key of the group schedules: the named instances each group
+/// is reconciled on. Groups without it are reconciled on every instance.
+fn parse_group_instances(schedule: &toml::Table, instances: &BTreeMap<String, GalaxySettings>) -> Result<HashMap<GroupName, BTreeSet<String>>> {
+    let mut group_instances = HashMap::new();
+    for (group_name, group_schedule) in schedule.iter() {
+        if let Some(names) = group_schedule.get("instances") {
+            let names = parse_instances(names, |name| instances.contains_key(name))
+                .with_context(|| format!("invalid instances of group {}", group_name))?;
+            group_instances.insert(group_name.parse()?, names);
+        }
+    }
+    Ok(group_instances)
+}
+
+/// Parses a list of instance names, each of which must be `declared`.
+pub(crate) fn parse_instances(names: &toml::Value, declared: impl Fn(&str) -> bool) -> Result<BTreeSet<String>> {
+    let names = names.as_array().ok_or(anyhow::anyhow!("instances must be a list of instance names"))?
+        .iter()
+        .map(|name| name.as_str().ok_or(anyhow::anyhow!("instance must be a string")))
+        .collect::<Result<BTreeSet<&str>>>()?;
+    if names.is_empty() {
+        return Err(anyhow::anyhow!("instances cannot be empty"));
+    }
+    if let Some(name) = names.iter().find(|name| !declared(name)) {
+        return Err(anyhow::anyhow!("instance {} is not declared", name));
+    }
+    Ok(names.into_iter().map(str::to_string).collect())
+}
+
+/// Parses the settings of a single Galaxy.
+///
+/// They are the Galaxy `url` and the API key, given by at most one of `api_key`,
+/// `api_key_file` or `api_key_command`.
+///
+/// # Arguments
+///
+/// * `galaxy` - The TOML table of the settings.
+///
+/// # Returns
+///
+/// A `Result` containing the `GalaxySettings`.
+pub(crate) fn parse_galaxy_settings(galaxy: &toml::Table) -> Result<GalaxySettings> {
+    let url = match galaxy.get("url") {
+        Some(url) => Some(crate::galaxy::parse_galaxy_url(url.as_str().ok_or(anyhow::anyhow!("url must be a string"))?)?),
+        None => None,
+    };
+    let mut sources = Vec::new();
+    for key in ["api_key", "api_key_file", "api_key_command"] {
+        if let Some(value) = galaxy.get(key) {
+            let value = value.as_str().ok_or(anyhow::anyhow!("{} must be a string", key))?.to_string();
+            sources.push(match key {
+                "api_key" => ApiKeySource::Value(value),
+                "api_key_file" => ApiKeySource::File(PathBuf::from(value)),
+                _ => ApiKeySource::Command(value),
+            });
+        }
+    }
+    if sources.len() > 1 {
+        return Err(anyhow::anyhow!("galaxy can only set one of api_key, api_key_file and api_key_command"));
+    }
+    Ok(GalaxySettings {
+        url,
+        api_key: sources.pop(),
+    })
+}
+
+/// Settings applying to schedule items that do not set their own.
+#[derive(Debug, Clone)]
+struct ScheduleDefaults {
+    timezone: Tz,
+    grace: Grace,
+    roles: Vec<RoleName>,
+}
+
+impl Default for ScheduleDefaults {
+    fn default() -> Self {
+        ScheduleDefaults {
+            timezone: Tz::UTC,
+            grace: Grace::default(),
+            roles: vec![DEFAULT_ROLE.parse().expect("valid role name")],
+        }
+    }
+}
+
+/// Parses the `timezone`, `lead` and `lag` keys of a table, falling back to `defaults`.
+///
+/// Roles are left to `parse_granted_roles`, since the top-level `roles` table declares roles.
+fn parse_schedule_defaults(table: &toml::Table, defaults: &ScheduleDefaults) -> Result<ScheduleDefaults> {
+    let timezone = match table.get("timezone") {
+        Some(timezone) => parse_timezone(timezone)?,
+        None => defaults.timezone,
+    };
+    let lead = match table.get("lead") {
+        Some(lead) => date::parse_duration(lead.as_str().ok_or(anyhow::anyhow!("lead must be a string"))?)?,
+        None => defaults.grace.lead,
+    };
+    let lag = match table.get("lag") {
+        Some(lag) => date::parse_duration(lag.as_str().ok_or(anyhow::anyhow!("lag must be a string"))?)?,
+        None => defaults.grace.lag,
+    };
+    Ok(ScheduleDefaults {
+        timezone,
+        grace: Grace {
+            lead,
+            lag,
+        },
+        roles: defaults.roles.clone(),
+    })
+}
+
+/// Parses the `roles` key of a group schedule or a schedule item, the list of
+/// roles it grants, falling back to `defaults`.
+fn parse_granted_roles(table: &toml::Table, defaults: &[RoleName]) -> Result<Vec<RoleName>> {
+    match table.get("roles") {
+        Some(roles) => roles.as_array().ok_or(anyhow::anyhow!("roles must be a list of role names"))?
+            .iter()
+            .map(|role| role.as_str().ok_or(anyhow::anyhow!("role must be a string"))?.parse())
+            .collect(),
+        None => Ok(defaults.to_vec()),
+    }
+}
+
+/// Mapping of group names to lists of schedule items.
+type Schedules = HashMap<GroupName, Vec<ScheduleItem>>;
+
 /// Parses the schedule section of the configuration.
 ///
+/// A group's schedule is either a list of schedule items, or a table with a
+/// `windows` list and settings shared by its items: `timezone`, `lead`, `lag`
+/// and the `roles` they grant.
+/// The table may also import events from a `calendar`, those whose summary or
+/// category is `match`, the group name by default.
+///
 /// # Arguments
 ///
 /// * `schedule` - The TOML table containing schedule information.
+/// * `defaults` - The settings of groups that do not set their own.
 ///
 /// # Returns
 ///
-/// A `Result` containing a `HashMap` of group names to lists of time ranges.
-fn parse_schedule(schedule: &toml::Table) -> Result<HashMap<GroupName, Vec<TimeRange>>> {
+/// A `Result` containing a `HashMap` of group names to lists of schedule items,
+/// and the calendars to load.
+fn parse_schedule(schedule: &toml::Table, defaults: &ScheduleDefaults) -> Result<(Schedules, Vec<CalendarSource>)> {
     let mut schedule_map = HashMap::new();
+    let mut calendars = Vec::new();
     for (group_name, schedules) in schedule.iter() {
-        let schedules = schedules.as_array().ok_or(anyhow::anyhow!("schedules not found"))?;        
-        let schedules: Vec<TimeRange> = schedules.iter().map(parse_schedule_item).collect::<Result<Vec<TimeRange>>>()?;
-        schedule_map.insert(group_name.parse()?, schedules);
+        let group_name: GroupName = group_name.parse()?;
+        let (schedules, defaults) = match schedules {
+            toml::Value::Table(group_schedule) => {
+                let mut group_defaults = parse_schedule_defaults(group_schedule, defaults)?;
+                group_defaults.roles = parse_granted_roles(group_schedule, &group_defaults.roles)?;
+                if let Some(calendar) = parse_calendar_source(&group_name, group_schedule, &group_defaults)? {
+                    calendars.push(calendar);
+                }
+                let windows = match group_schedule.get("windows") {
+                    Some(windows) => windows.as_array().ok_or(anyhow::anyhow!("windows not found"))?.as_slice(),
+                    None if group_schedule.contains_key("calendar") => &[],
+                    None => return Err(anyhow::anyhow!("windows not found")),
+                };
+                (windows, group_defaults)
+            },
+            schedules => (schedules.as_array().ok_or(anyhow::anyhow!("schedules not found"))?.as_slice(), defaults.clone()),
+        };
+        let schedules: Vec<ScheduleItem> = schedules.iter().map(|schedule| parse_schedule_item(schedule, &defaults)).collect::<Result<Vec<ScheduleItem>>>()?;
+        schedule_map.insert(group_name, schedules);
     }
-    Ok(schedule_map)
+    Ok((schedule_map, calendars))
+}
+
+/// Parses the `calendar` and `match` keys of a group's schedule table.
+///
+/// Returns `None` if the group does not import a calendar.
+fn parse_calendar_source(group_name: &GroupName, group_schedule: &toml::Table, defaults: &ScheduleDefaults) -> Result<Option<CalendarSource>> {
+    let Some(location) = group_schedule.get("calendar") else {
+        return Ok(None);
+    };
+    let pattern = match group_schedule.get("match") {
+        Some(pattern) => pattern.as_str().ok_or(anyhow::anyhow!("match must be a string"))?.to_string(),
+        None => group_name.to_string(),
+    };
+    Ok(Some(CalendarSource {
+        group: group_name.clone(),
+        location: location.as_str().ok_or(anyhow::anyhow!("calendar must be a string"))?.to_string(),
+        pattern,
+        timezone: defaults.timezone,
+        grace: defaults.grace,
+        roles: defaults.roles.clone(),
+    }))
 }
 
 /// Parses a single schedule item.
 ///
+/// `from` and `to` are dates, local datetimes or RFC 3339 datetimes, given as strings
+/// or as TOML datetimes. An item may set its own `timezone` and `roles`, and a
+/// `repeat` rule making `from` and `to` the first occurrence of a recurrence.
+///
 /// # Arguments
 ///
 /// * `schedule` - The TOML value representing a single schedule item.
+/// * `defaults` - The settings of the item's group.
 ///
 /// # Returns
 ///
-/// A `Result` containing a `TimeRange`.
-fn parse_schedule_item(schedule: &toml::Value) -> Result<TimeRange> {
+/// A `Result` containing a `ScheduleItem`.
+fn parse_schedule_item(schedule: &toml::Value, defaults: &ScheduleDefaults) -> Result<ScheduleItem> {
     let schedule = schedule.as_table().ok_or(anyhow::anyhow!("schedule not found"))?;
-    let from = schedule.get("from").ok_or(anyhow::anyhow!("from not found"))?.as_str().ok_or(anyhow::anyhow!("from not found"))?;
-    let to = schedule.get("to").ok_or(anyhow::anyhow!("to not found"))?.as_str().ok_or(anyhow::anyhow!("to not found"))?;
-    Ok(TimeRange {
-        from: from.parse::<Date>()?,
-        to: to.parse::<Date>()?,
+    let from = schedule.get("from").ok_or(anyhow::anyhow!("from not found"))?;
+    let to = schedule.get("to").ok_or(anyhow::anyhow!("to not found"))?;
+    let timezone = match schedule.get("timezone") {
+        Some(timezone) => parse_timezone(timezone)?,
+        None => defaults.timezone,
+    };
+    let time_range = TimeRange {
+        from: parse_moment(from).ok_or(anyhow::anyhow!("from not found"))??,
+        to: parse_moment(to).ok_or(anyhow::anyhow!("to not found"))??,
+        timezone,
+        grace: defaults.grace,
+        roles: parse_granted_roles(schedule, &defaults.roles)?,
+    };
+    match schedule.get("repeat") {
+        Some(repeat) => Ok(ScheduleItem::Recurring(parse_recurrence(repeat, time_range)?)),
+        None => Ok(ScheduleItem::Once(time_range)),
+    }
+}
+
+/// Parses the `repeat` rule of a schedule item.
+///
+/// # Arguments
+///
+/// * `repeat` - The TOML table with `frequency`, and optionally `interval`, `weekdays`,
+///   `count` or `until`, and `except`.
+/// * `first` - The first occurrence.
+///
+/// # Returns
+///
+/// A `Result` containing a `Recurrence`.
+fn parse_recurrence(repeat: &toml::Value, first: TimeRange) -> Result<Recurrence> {
+    let repeat = repeat.as_table().ok_or(anyhow::anyhow!("repeat must be a table"))?;
+    let frequency = repeat.get("frequency").ok_or(anyhow::anyhow!("frequency not found"))?
+        .as_str().ok_or(anyhow::anyhow!("frequency must be a string"))?
+        .parse::<Frequency>()?;
+    let interval = match repeat.get("interval") {
+        Some(interval) => interval.as_integer().filter(|interval| *interval >= 1).ok_or(anyhow::anyhow!("interval must be a positive integer"))? as u32,
+        None => 1,
+    };
+    let weekdays = match repeat.get("weekdays") {
+        Some(weekdays) => weekdays.as_array().ok_or(anyhow::anyhow!("weekdays must be an array"))?
+            .iter()
+            .map(|weekday| weekday.as_str().ok_or(anyhow::anyhow!("weekday must be a string")).and_then(schedule::parse_weekday))
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+    let count = match repeat.get("count") {
+        Some(count) => Some(count.as_integer().filter(|count| *count >= 1).ok_or(anyhow::anyhow!("count must be a positive integer"))? as u32),
+        None => None,
+    };
+    let until = match repeat.get("until") {
+        Some(until) => Some(parse_moment(until).ok_or(anyhow::anyhow!("until must be a date"))??.date(&first.timezone)),
+        None => None,
+    };
+    if count.is_some() && until.is_some() {
+        return Err(anyhow::anyhow!("repeat cannot have both count and until"));
+    }
+    let except = match repeat.get("except") {
+        Some(except) => except.as_array().ok_or(anyhow::anyhow!("except must be an array"))?
+            .iter()
+            .map(|day| Ok(parse_moment(day).ok_or(anyhow::anyhow!("except must contain dates"))??.date(&first.timezone)))
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+    Ok(Recurrence {
+        first,
+        frequency,
+        interval,
+        weekdays,
+        count,
+        until,
+        except,
     })
 }
 
-/// Reads the configuration from a file.
+/// Parses a schedule bound given as a string or a TOML datetime.
+///
+/// Returns `None` if the value is of another type.
+fn parse_moment(value: &toml::Value) -> Option<Result<Moment>> {
+    match value {
+        toml::Value::String(moment) => Some(moment.parse()),
+        toml::Value::Datetime(moment) => Some(moment.to_string().parse()),
+        _ => None,
+    }
+}
+
+/// Parses an IANA timezone name, such as "America/Montreal".
+fn parse_timezone(value: &toml::Value) -> Result<Tz> {
+    let timezone = value.as_str().ok_or(anyhow::anyhow!("timezone must be a string"))?;
+    timezone.parse::<Tz>().map_err(|e| anyhow::anyhow!("invalid timezone {}: {}", timezone, e))
+}
+
+/// Reads the configuration from a file and loads its calendars.
+///
+/// The configuration is validated first, and rejected with a `ValidationError`
+/// listing every error if it has any. Its warnings are logged. Relative calendar,
+/// state and API key file paths are resolved against the directory of the
+/// configuration file.
 ///
 /// # Arguments
 ///
 /// * `path` - The path to the configuration file.
+/// * `client` - The timeouts and TLS settings of the requests fetching calendars.
 ///
 /// # Returns
 ///
 /// A `Result` containing the parsed `ConfigFile`.
-pub fn read_config(path: &str) -> Result<ConfigFile> {
-    let config = std::fs::read_to_string(path)?;
-    config.parse()
+pub async fn read_config(path: &str, client: &ClientSettings) -> Result<ConfigFile> {
+    let source = std::fs::read_to_string(path)?;
+    let diagnostics = validate::validate(&source, Utc::now());
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        return Err(anyhow::Error::new(ValidationError { diagnostics }).context(format!("{} is invalid", path)));
+    }
+    for diagnostic in diagnostics.iter() {
+        tracing::warn!(path, line = diagnostic.line, column = diagnostic.column, hint = diagnostic.hint, "{}", diagnostic.message);
+    }
+    load_config(path, &source, client).await
+}
+
+/// Parses the content of a configuration file already validated, and loads its calendars.
+///
+/// # Arguments
+///
+/// * `path` - The path to the configuration file, relative paths are resolved against its directory.
+/// * `source` - The content of the configuration file.
+/// * `client` - The timeouts and TLS settings of the requests fetching calendars.
+pub async fn load_config(path: &str, source: &str, client: &ClientSettings) -> Result<ConfigFile> {
+    let mut config: ConfigFile = source.parse()?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+    let http = galaxy::http_client(client)?;
+    config.load_calendars(base_dir, &http).await.with_context(|| format!("cannot load the calendars of {}", path))?;
+    config.state = Some(match config.state.take() {
+        Some(state) => base_dir.join(state),
+        None => Path::new(path).with_extension("state.json"),
+    });
+    for settings in std::iter::once(&mut config.galaxy).chain(config.instances.values_mut()) {
+        if let Some(ApiKeySource::File(api_key_file)) = &mut settings.api_key {
+            *api_key_file = base_dir.join(&api_key_file);
+        }
+    }
+    Ok(config)
 }
 
 #[cfg(test)]
@@ -211,15 +756,80 @@ mod tests {
 
     const TEST_CONFIG_FILE: &str = "./src/sample.toml";
 
+    fn instant(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn once(schedule_item: &ScheduleItem) -> &TimeRange {
+        match schedule_item {
+            ScheduleItem::Once(time_range) => time_range,
+            schedule_item => panic!("unexpected schedule item {:?}", schedule_item),
+        }
+    }
+
     #[test]
     fn test_time_range_contains() {
         let range = TimeRange {
-            from: Date::from_ymd(2023, 1, 1),
-            to: Date::from_ymd(2023, 12, 31),
+            from: "2023-01-01".parse().unwrap(),
+            to: "2023-12-31".parse().unwrap(),
+            timezone: Tz::UTC,
+            grace: Grace::default(),
+            roles: Vec::new(),
         };
-        assert!(range.contains(&Date::from_ymd(2023, 6, 15)));
-        assert!(!range.contains(&Date::from_ymd(2022, 12, 31)));
-        assert!(!range.contains(&Date::from_ymd(2024, 1, 1)));
+        assert!(range.contains(&instant("2023-06-15T12:00:00Z")));
+        assert!(range.contains(&instant("2023-12-31T23:59:59Z")));
+        assert!(!range.contains(&instant("2022-12-31T12:00:00Z")));
+        assert!(!range.contains(&instant("2024-01-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_time_range_contains_in_timezone() {
+        let range = TimeRange {
+            from: "2024-05-01".parse().unwrap(),
+            to: "2024-05-03".parse().unwrap(),
+            timezone: "America/Montreal".parse().unwrap(),
+            grace: Grace::default(),
+            roles: Vec::new(),
+        };
+        // 20:00 in Montreal the day before starts on the 1st in UTC.
+        assert!(!range.contains(&instant("2024-05-01T00:00:00Z")));
+        assert!(range.contains(&instant("2024-05-01T04:00:00Z")));
+        assert!(range.contains(&instant("2024-05-04T03:59:59Z")));
+        assert!(!range.contains(&instant("2024-05-04T04:00:00Z")));
+    }
+
+    #[test]
+    fn test_time_range_next_transition() {
+        let range = TimeRange {
+            from: "2023-01-10".parse().unwrap(),
+            to: "2023-01-12".parse().unwrap(),
+            timezone: Tz::UTC,
+            grace: Grace::default(),
+            roles: Vec::new(),
+        };
+        let start = "2023-01-10T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let end = "2023-01-13T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(range.next_transition("2023-01-01T12:00:00Z".parse().unwrap()), Some(start));
+        assert_eq!(range.next_transition(start), Some(end));
+        assert_eq!(range.next_transition(end), None);
+    }
+
+    #[test]
+    fn test_config_next_transition() -> Result<()> {
+        let config: ConfigFile = r#"
+        [groups]
+        team_a = ["alice@example.com"]
+        team_b = ["bob@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2023-01-10", to = "2023-01-12" }]
+        team_b = [{ from = "2023-01-11", to = "2023-01-20" }]
+        "#.parse()?;
+        let next = |after: &str| config.next_transition(after.parse().unwrap()).map(|instant| instant.to_rfc3339());
+        assert_eq!(next("2023-01-10T08:00:00Z"), Some("2023-01-11T00:00:00+00:00".to_string()));
+        assert_eq!(next("2023-01-11T00:00:00Z"), Some("2023-01-13T00:00:00+00:00".to_string()));
+        assert_eq!(next("2023-01-21T00:00:00Z"), None);
+        Ok(())
     }
 
     #[test]
@@ -250,16 +860,330 @@ mod tests {
     }
 
     #[test]
-    fn test_read_config() -> Result<()> {
-        let config = read_config(TEST_CONFIG_FILE)?;
+    fn test_parse_config_with_timezones() -> Result<()> {
+        let config_str = r#"
+        timezone = "America/Montreal"
+
+        [groups]
+        team_a = ["alice@example.com"]
+        team_b = ["bob@example.com"]
+
+        [schedule]
+        team_a = [
+            { from = "2024-05-01T09:00", to = "2024-05-01T17:00" },
+            { from = 2024-05-02T09:00:00, to = "2024-05-02T17:00:00-04:00" },
+            { from = "2024-05-03", to = "2024-05-03", timezone = "UTC" },
+        ]
+        team_b = { timezone = "Europe/Paris", windows = [
+            { from = "2024-05-01T09:00", to = "2024-05-01T17:00" },
+        ] }
+        "#;
+
+        let config: ConfigFile = ConfigFile::from_str(config_str)?;
+        let team_a = config.schedule.get(&"team_a".parse()?).unwrap();
+        assert_eq!(once(&team_a[0]).start(), instant("2024-05-01T13:00:00Z"));
+        assert_eq!(once(&team_a[0]).end(), instant("2024-05-01T21:00:00Z"));
+        assert_eq!(once(&team_a[1]).start(), instant("2024-05-02T13:00:00Z"));
+        assert_eq!(once(&team_a[1]).end(), instant("2024-05-02T21:00:00Z"));
+        assert_eq!(once(&team_a[2]).start(), instant("2024-05-03T00:00:00Z"));
+        let team_b = config.schedule.get(&"team_b".parse()?).unwrap();
+        assert_eq!(once(&team_b[0]).start(), instant("2024-05-01T07:00:00Z"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_recurring_schedule() -> Result<()> {
+        let config_str = r#"
+        timezone = "America/Montreal"
+
+        [groups]
+        course = ["alice@example.com"]
+
+        [schedule]
+        course = [
+            { from = "2024-09-03T13:00", to = "2024-09-03T16:00", repeat = { frequency = "weekly", weekdays = ["tue", "thu"], count = 28, except = ["2024-10-15"] } },
+            { from = "2024-12-16", to = "2024-12-16", repeat = { frequency = "daily", until = "2024-12-20" } },
+        ]
+        "#;
+
+        let config: ConfigFile = ConfigFile::from_str(config_str)?;
+        let course = config.schedule.get(&"course".parse()?).unwrap();
+        assert!(matches!(course[0], ScheduleItem::Recurring(_)));
+        assert!(course[0].contains(&instant("2024-10-10T18:00:00Z")));
+        assert!(!course[0].contains(&instant("2024-10-15T18:00:00Z")));
+        assert!(course[1].contains(&instant("2024-12-20T12:00:00Z")));
+        assert!(!course[1].contains(&instant("2024-12-21T12:00:00Z")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_recurrence() {
+        for repeat in [
+            r#"{ frequency = "yearly" }"#,
+            r#"{ frequency = "weekly", weekdays = ["someday"] }"#,
+            r#"{ frequency = "weekly", interval = 0 }"#,
+            r#"{ frequency = "daily", count = 3, until = "2024-02-01" }"#,
+        ] {
+            let config_str = format!(r#"
+            [groups]
+            course = ["alice@example.com"]
+
+            [schedule]
+            course = [{{ from = "2024-01-01", to = "2024-01-01", repeat = {} }}]
+            "#, repeat);
+            assert!(ConfigFile::from_str(&config_str).is_err(), "{}", repeat);
+        }
+    }
+
+    #[test]
+    fn test_parse_grace_periods() -> Result<()> {
+        let config_str = r#"
+        lead = "1h"
+
+        [groups]
+        team_a = ["alice@example.com"]
+        team_b = ["bob@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2024-05-01", to = "2024-05-01" }]
+        team_b = { lag = "1d", windows = [{ from = "2024-05-01", to = "2024-05-01" }] }
+        "#;
+
+        let config: ConfigFile = ConfigFile::from_str(config_str)?;
+        let team_a = once(&config.schedule.get(&"team_a".parse()?).unwrap()[0]);
+        assert_eq!(team_a.grace, Grace { lead: TimeDelta::hours(1), lag: TimeDelta::zero() });
+        assert_eq!(team_a.effective_start(), instant("2024-04-30T23:00:00Z"));
+        let team_b = once(&config.schedule.get(&"team_b".parse()?).unwrap()[0]);
+        assert_eq!(team_b.grace, Grace { lead: TimeDelta::hours(1), lag: TimeDelta::days(1) });
+        assert_eq!(team_b.effective_end(), instant("2024-05-03T00:00:00Z"));
+        assert!(ConfigFile::from_str(&config_str.replace("\"1h\"", "\"soon\"")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_timezone() {
+        let config_str = r#"
+        timezone = "Mars/Olympus_Mons"
+
+        [groups]
+        team_a = ["alice@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2024-05-01", to = "2024-05-02" }]
+        "#;
+        assert!(ConfigFile::from_str(config_str).is_err());
+    }
+
+    #[test]
+    fn test_parse_calendar_source() -> Result<()> {
+        let config_str = r#"
+        [groups]
+        team_a = ["alice@example.com"]
+        team_b = ["bob@example.com"]
+
+        [schedule]
+        team_a = { calendar = "workshops.ics", lead = "1h" }
+        team_b = { calendar = "https://example.com/training.ics", match = "GPU", windows = [{ from = "2024-05-01", to = "2024-05-02" }] }
+        "#;
+
+        let config: ConfigFile = ConfigFile::from_str(config_str)?;
+        let mut calendars = config.calendars.clone();
+        calendars.sort_by(|a, b| a.group.cmp(&b.group));
+        assert_eq!(calendars.len(), 2);
+        assert_eq!(calendars[0].pattern, "team_a");
+        assert_eq!(calendars[0].grace.lead, TimeDelta::hours(1));
+        assert_eq!(calendars[1].pattern, "GPU");
+        assert!(config.schedule.get(&"team_a".parse()?).unwrap().is_empty());
+        assert_eq!(config.schedule.get(&"team_b".parse()?).unwrap().len(), 1);
+        assert!(ConfigFile::from_str(&config_str.replace("\"workshops.ics\"", "1")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_missing_user_policy() -> Result<()> {
+        let config_str = r#"
+        [groups]
+        team_a = ["alice@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2024-05-01", to = "2024-05-02" }]
+        "#;
+        assert_eq!(ConfigFile::from_str(config_str)?.missing_users, MissingUserPolicy::Fail);
+        let config: ConfigFile = format!("missing_users = \"skip\"\n{}", config_str).parse()?;
+        assert_eq!(config.missing_users, MissingUserPolicy::Skip);
+        assert!(ConfigFile::from_str(&format!("missing_users = \"ignore\"\n{}", config_str)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_reconcile_mode() -> Result<()> {
+        let config_str = r#"
+        [groups]
+        team_a = ["alice@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2024-05-01", to = "2024-05-02" }]
+        "#;
+        assert_eq!(ConfigFile::from_str(config_str)?.reconcile, ReconcileMode::Replace);
+        let config: ConfigFile = format!("reconcile = \"preserve\"\n{}", config_str).parse()?;
+        assert_eq!(config.reconcile, ReconcileMode::Preserve);
+        assert!(ConfigFile::from_str(&format!("reconcile = \"merge\"\n{}", config_str)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_roles() -> Result<()> {
+        let config_str = r#"
+        [roles]
+        gpu-training = "Training on GPU nodes"
+        cpu-training = { description = "Training on CPU nodes" }
+
+        [groups]
+        team_a = ["alice@example.com"]
+
+        [schedule]
+        team_a = { roles = ["gpu-training"], windows = [
+            { from = "2024-05-01", to = "2024-05-02" },
+            { from = "2024-06-01", to = "2024-06-02", roles = ["cpu-training", "gpu-training"] },
+        ] }
+        "#;
+        let config = ConfigFile::from_str(config_str)?;
+        assert_eq!(config.roles.len(), 2);
+        assert_eq!(config.roles[&"cpu-training".parse()?], "Training on CPU nodes");
+        let schedule = config.schedule.get(&"team_a".parse()?).unwrap();
+        assert_eq!(schedule[0].roles(), &["gpu-training".parse()?]);
+        assert_eq!(schedule[1].roles(), &["cpu-training".parse()?, "gpu-training".parse()?]);
+
+        let config = ConfigFile::from_str(&config_str.replace("roles = [\"gpu-training\"], ", ""))?;
+        assert_eq!(config.schedule.get(&"team_a".parse()?).unwrap()[0].roles(), &["training".parse()?]);
+        assert!(ConfigFile::from_str(&config_str.replace("[\"gpu-training\"]", "\"gpu-training\"")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_config() -> Result<()> {
+        let config = read_config(TEST_CONFIG_FILE, &ClientSettings::default()).await?;
         assert!(!config.groups.is_empty());
         assert!(!config.schedule.is_empty());
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_config_with_calendar() -> Result<()> {
+        let config = read_config("./src/fixtures/calendar.toml", &ClientSettings::default()).await?;
+        let workshop = config.schedule.get(&"rna-seq workshop".parse()?).unwrap();
+        assert_eq!(workshop.len(), 3);
+        assert!(workshop.iter().any(|item| item.contains(&instant("2024-06-11T12:00:00Z"))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_invalid_config() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("training-manager-config-invalid-{}.toml", std::process::id()));
+        std::fs::write(&path, "[groups]\nteam_a = [\"alice@example.com\"]\n\n[schedule]\n")?;
+        let error = read_config(&path.to_string_lossy(), &ClientSettings::default()).await.unwrap_err();
+        std::fs::remove_file(&path)?;
+        let error = error.downcast_ref::<ValidationError>().unwrap();
+        assert_eq!(error.to_string(), "2:1: error: group team_a has no schedule\n    hint: add `team_a = [{ from = ..., to = ... }]` to [schedule]");
+        Ok(())
+    }
+
+    /// A log writer keeping what is written, to check the events logged.
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_config_logs_warnings() -> Result<()> {
+        use tracing::instrument::WithSubscriber;
+        let path = std::env::temp_dir().join(format!("training-manager-config-warnings-{}.toml", std::process::id()));
+        std::fs::write(&path, format!("[galaxy]\nurl = \"https://galaxy.example.org\"\napi_key = \"secret\"\n\n{}", std::fs::read_to_string(TEST_CONFIG_FILE)?))?;
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+        let config = read_config(&path.to_string_lossy(), &ClientSettings::default()).with_subscriber(subscriber).await;
+        std::fs::remove_file(&path)?;
+        config?;
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert!(logs.contains("WARN"), "{}", logs);
+        assert!(logs.contains("line=3"), "{}", logs);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_config_state() -> Result<()> {
+        let config = read_config(TEST_CONFIG_FILE, &ClientSettings::default()).await?;
+        assert_eq!(config.state, Some(PathBuf::from("./src/sample.state.json")));
+        let config: ConfigFile = format!("state = \"state.json\"\n{}", std::fs::read_to_string(TEST_CONFIG_FILE)?).parse()?;
+        assert_eq!(config.state, Some(PathBuf::from("state.json")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_config_galaxy() -> Result<()> {
+        let config = read_config(TEST_CONFIG_FILE, &ClientSettings::default()).await?;
+        assert_eq!(config.galaxy, GalaxySettings::default());
+        let path = std::env::temp_dir().join(format!("training-manager-config-galaxy-{}.toml", std::process::id()));
+        std::fs::write(&path, format!("[galaxy]\nurl = \"https://galaxy.example.org/\"\napi_key_file = \"api_key\"\n\n{}", std::fs::read_to_string(TEST_CONFIG_FILE)?))?;
+        let config = read_config(&path.to_string_lossy(), &ClientSettings::default()).await;
+        std::fs::remove_file(&path)?;
+        let config = config?;
+        assert_eq!(config.galaxy.url.as_deref(), Some("https://galaxy.example.org"));
+        assert_eq!(config.galaxy.api_key, Some(ApiKeySource::File(std::env::temp_dir().join("api_key"))));
+        let config: ConfigFile = format!("[galaxy]\napi_key_command = \"pass galaxy\"\n\n{}", std::fs::read_to_string(TEST_CONFIG_FILE)?).parse()?;
+        assert_eq!(config.galaxy.api_key, Some(ApiKeySource::Command("pass galaxy".to_string())));
+        Ok(())
+    }
+
     #[test]
-    fn test_read_config_not_found() {
-        let config = read_config("not_found.toml");
+    fn test_parse_instances() -> Result<()> {
+        let config_str = r#"
+        [galaxy.main]
+        url = "https://galaxy.example.org"
+        api_key_file = "main.key"
+
+        [galaxy.gpu]
+        url = "https://gpu.galaxy.example.org"
+        api_key_command = "pass galaxy/gpu"
+
+        [groups]
+        team_a = ["alice@example.com"]
+        team_b = ["bob@example.com"]
+
+        [schedule]
+        team_a = [{ from = "2023-01-01", to = "2023-12-31" }]
+        team_b = { instances = ["gpu"], windows = [{ from = "2023-01-01", to = "2023-12-31" }] }
+        "#;
+        let mut config = ConfigFile::from_str(config_str)?;
+        assert_eq!(config.galaxy, GalaxySettings::default());
+        assert_eq!(config.instances.keys().collect::<Vec<_>>(), vec!["gpu", "main"]);
+        assert_eq!(config.instances["gpu"].api_key, Some(ApiKeySource::Command("pass galaxy/gpu".to_string())));
+        let (team_a, team_b) = ("team_a".parse()?, "team_b".parse()?);
+        assert!(config.applies_to(&team_a, Some("main")) && config.applies_to(&team_a, Some("gpu")));
+        assert!(!config.applies_to(&team_b, Some("main")) && config.applies_to(&team_b, Some("gpu")));
+        assert!(config.applies_to(&team_b, None));
+
+        config.state = Some(PathBuf::from("conf/training.state.json"));
+        assert_eq!(config.state_for(None), Some(PathBuf::from("conf/training.state.json")));
+        assert_eq!(config.state_for(Some("gpu")), Some(PathBuf::from("conf/training.state.gpu.json")));
+
+        assert!(ConfigFile::from_str(&config_str.replace("[\"gpu\"]", "[\"staging\"]")).is_err());
+        assert!(ConfigFile::from_str(&config_str.replace("api_key_file = \"main.key\"", "")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_config_not_found() {
+        let config = read_config("not_found.toml", &ClientSettings::default()).await;
         assert!(config.is_err());
     }
 
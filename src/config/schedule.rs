@@ -0,0 +1,367 @@
+//! # Schedule Module
+//!
+//! Evaluation of schedule items. An item is either a one-off `TimeRange` or a
+//! `Recurrence` repeating a time range, such as a course every Tuesday and Thursday.
+//!
+//! ## Example
+//!
+//! ```toml
+//! [schedule]
+//! course = [
+//!     { from = "2024-09-03T13:00", to = "2024-09-03T16:00", repeat = { frequency = "weekly", weekdays = ["tue", "thu"], count = 28, except = ["2024-10-15"] } },
+//! ]
+//! ```
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc, Weekday};
+use std::str::FromStr;
+use crate::config::TimeRange;
+use crate::galaxy::types::RoleName;
+
+/// Tells when a group is in training.
+///
+/// Schedules are evaluated on effective windows, that is time ranges widened by their grace periods.
+pub trait Schedule {
+    /// Checks if a given instant is within the schedule.
+    fn contains(&self, instant: &DateTime<Utc>) -> bool;
+
+    /// Returns the first instant strictly after `after` at which the schedule starts or ends.
+    fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>>;
+
+    /// Returns the first time range whose effective window ends after `after`.
+    ///
+    /// That is the range containing `after` if there is one, otherwise the next one.
+    fn next_window(&self, after: &DateTime<Utc>) -> Option<TimeRange>;
+}
+
+impl Schedule for TimeRange {
+    /// Checks if a given instant is within the time range.
+    ///
+    /// # Arguments
+    ///
+    /// * `instant` - The instant to check.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the instant is within the range, `false` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use your_crate_name::config::{Grace, TimeRange, schedule::Schedule};
+    ///
+    /// let range = TimeRange {
+    ///     from: "2023-01-01".parse().unwrap(),
+    ///     to: "2023-12-31".parse().unwrap(),
+    ///     timezone: chrono_tz::UTC,
+    ///     grace: Grace::default(),
+    ///     roles: Vec::new(),
+    /// };
+    /// assert!(range.contains(&"2023-06-15T12:00:00Z".parse().unwrap()));
+    /// assert!(!range.contains(&"2024-01-01T00:00:00Z".parse().unwrap()));
+    /// ```
+    fn contains(&self, instant: &DateTime<Utc>) -> bool {
+        *instant >= self.effective_start() && *instant < self.effective_end()
+    }
+
+    fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        [self.effective_start(), self.effective_end()].into_iter().filter(|instant| *instant > after).min()
+    }
+
+    fn next_window(&self, after: &DateTime<Utc>) -> Option<TimeRange> {
+        Some(self.clone()).filter(|time_range| time_range.effective_end() > *after)
+    }
+}
+
+/// A single entry of a group's schedule.
+#[derive(Debug)]
+pub enum ScheduleItem {
+    Once(TimeRange),
+    Recurring(Recurrence),
+}
+
+impl ScheduleItem {
+    /// Returns the roles granted during the item's windows.
+    pub fn roles(&self) -> &[RoleName] {
+        match self {
+            ScheduleItem::Once(time_range) => &time_range.roles,
+            ScheduleItem::Recurring(recurrence) => &recurrence.first.roles,
+        }
+    }
+}
+
+impl Schedule for ScheduleItem {
+    fn contains(&self, instant: &DateTime<Utc>) -> bool {
+        match self {
+            ScheduleItem::Once(time_range) => time_range.contains(instant),
+            ScheduleItem::Recurring(recurrence) => recurrence.contains(instant),
+        }
+    }
+
+    fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleItem::Once(time_range) => time_range.next_transition(after),
+            ScheduleItem::Recurring(recurrence) => recurrence.next_transition(after),
+        }
+    }
+
+    fn next_window(&self, after: &DateTime<Utc>) -> Option<TimeRange> {
+        match self {
+            ScheduleItem::Once(time_range) => time_range.next_window(after),
+            ScheduleItem::Recurring(recurrence) => recurrence.next_window(after),
+        }
+    }
+}
+
+/// How often a recurrence repeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+impl FromStr for Frequency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Frequency::Daily),
+            "weekly" => Ok(Frequency::Weekly),
+            _ => Err(anyhow!("invalid frequency {}, expected daily or weekly", s)),
+        }
+    }
+}
+
+/// A time range repeated following an RRULE-like rule.
+///
+/// `first` is the first occurrence, even on a day `weekdays` leaves out, like the
+/// DTSTART of an RRULE. Later occurrences keep its wall-clock times and duration. Like RRULE, `count` includes the occurrences removed by `except`.
+#[derive(Debug)]
+pub struct Recurrence {
+    pub first: TimeRange,
+    pub frequency: Frequency,
+    /// Repeat every `interval` days or weeks.
+    pub interval: u32,
+    /// Days of the week of weekly occurrences, the day of `first` when empty.
+    pub weekdays: Vec<Weekday>,
+    pub count: Option<u32>,
+    /// Last day an occurrence may start on.
+    pub until: Option<NaiveDate>,
+    /// Days on which no occurrence starts.
+    pub except: Vec<NaiveDate>,
+}
+
+impl Recurrence {
+    /// Returns the occurrences in chronological order. The iterator is infinite without `count` or `until`.
+    pub fn occurrences(&self) -> impl Iterator<Item = TimeRange> + '_ {
+        let first_day = self.first.from.date(&self.first.timezone);
+        let days = std::iter::successors(Some(first_day), |day| day.checked_add_days(Days::new(1)));
+        let until = self.until;
+        days.take_while(move |day| until.is_none_or(|until| *day <= until))
+            .filter(move |day| self.matches(first_day, day))
+            .take(self.count.map(|count| count as usize).unwrap_or(usize::MAX))
+            .filter(|day| !self.except.contains(day))
+            .map(move |day| {
+                let offset = (day - first_day).num_days() as u64;
+                TimeRange {
+                    from: self.first.from.add_days(offset, &self.first.timezone),
+                    to: self.first.to.add_days(offset, &self.first.timezone),
+                    timezone: self.first.timezone,
+                    grace: self.first.grace,
+                    roles: self.first.roles.clone(),
+                }
+            })
+    }
+
+    /// Checks if an occurrence starts on `day`, ignoring `count`, `until` and `except`.
+    fn matches(&self, first_day: NaiveDate, day: &NaiveDate) -> bool {
+        if *day == first_day {
+            return true;
+        }
+        let interval = self.interval.max(1) as i64;
+        match self.frequency {
+            Frequency::Daily => (*day - first_day).num_days() % interval == 0,
+            Frequency::Weekly => {
+                let week_start = |day: &NaiveDate| *day - Days::new(day.weekday().num_days_from_monday() as u64);
+                let weeks = (week_start(day) - week_start(&first_day)).num_days() / 7;
+                let weekday_matches = if self.weekdays.is_empty() {
+                    day.weekday() == first_day.weekday()
+                } else {
+                    self.weekdays.contains(&day.weekday())
+                };
+                weeks % interval == 0 && weekday_matches
+            },
+        }
+    }
+}
+
+impl Schedule for Recurrence {
+    fn contains(&self, instant: &DateTime<Utc>) -> bool {
+        self.occurrences()
+            .take_while(|occurrence| occurrence.effective_start() <= *instant)
+            .any(|occurrence| occurrence.contains(instant))
+    }
+
+    fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next: Option<DateTime<Utc>> = None;
+        for occurrence in self.occurrences() {
+            if let Some(transition) = occurrence.next_transition(after) {
+                next = Some(next.map_or(transition, |next| next.min(transition)));
+            }
+            // Later occurrences start after this one, they cannot transition sooner.
+            if occurrence.effective_start() > after {
+                break;
+            }
+        }
+        next
+    }
+
+    fn next_window(&self, after: &DateTime<Utc>) -> Option<TimeRange> {
+        self.occurrences().find(|occurrence| occurrence.effective_end() > *after)
+    }
+}
+
+/// Parses a weekday name, such as "tue" or "Tuesday".
+pub fn parse_weekday(s: &str) -> Result<Weekday> {
+    s.parse::<Weekday>().map_err(|_| anyhow!("invalid weekday {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Grace;
+    use chrono_tz::Tz;
+
+    fn instant(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn course() -> Recurrence {
+        Recurrence {
+            first: TimeRange {
+                from: "2024-09-03T13:00".parse().unwrap(),
+                to: "2024-09-03T16:00".parse().unwrap(),
+                timezone: "America/Montreal".parse().unwrap(),
+                grace: Grace::default(),
+                roles: Vec::new(),
+            },
+            frequency: Frequency::Weekly,
+            interval: 1,
+            weekdays: vec![Weekday::Tue, Weekday::Thu],
+            count: Some(28),
+            until: None,
+            except: vec![NaiveDate::from_ymd_opt(2024, 10, 15).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_time_range_grace() {
+        let range = TimeRange {
+            from: "2024-05-01T09:00".parse().unwrap(),
+            to: "2024-05-01T17:00".parse().unwrap(),
+            timezone: Tz::UTC,
+            grace: Grace {
+                lead: chrono::TimeDelta::minutes(30),
+                lag: chrono::TimeDelta::hours(2),
+            },
+            roles: Vec::new(),
+        };
+        assert!(range.contains(&instant("2024-05-01T08:30:00Z")));
+        assert!(!range.contains(&instant("2024-05-01T08:29:59Z")));
+        assert!(range.contains(&instant("2024-05-01T18:59:59Z")));
+        assert_eq!(range.next_transition(instant("2024-05-01T12:00:00Z")), Some(instant("2024-05-01T19:00:00Z")));
+        assert!(range.next_window(&instant("2024-05-01T18:00:00Z")).is_some());
+        assert!(range.next_window(&instant("2024-05-01T19:00:00Z")).is_none());
+    }
+
+    #[test]
+    fn test_weekly_occurrences() {
+        let course = course();
+        let occurrences: Vec<TimeRange> = course.occurrences().collect();
+        assert_eq!(occurrences.len(), 27);
+        assert_eq!(occurrences[1].start(), instant("2024-09-05T17:00:00Z"));
+        // After the end of daylight saving time, 13:00 in Montreal is 18:00 UTC.
+        assert_eq!(occurrences.last().unwrap().start(), instant("2024-12-05T18:00:00Z"));
+    }
+
+    #[test]
+    fn test_first_occurrence_on_another_weekday() {
+        let mut course = course();
+        // Monday, while the course is on Tuesdays and Thursdays.
+        course.first.from = "2024-09-02T13:00".parse().unwrap();
+        course.first.to = "2024-09-02T16:00".parse().unwrap();
+        course.count = Some(3);
+        let starts: Vec<DateTime<Utc>> = course.occurrences().map(|occurrence| occurrence.start()).collect();
+        assert_eq!(starts, vec![instant("2024-09-02T17:00:00Z"), instant("2024-09-03T17:00:00Z"), instant("2024-09-05T17:00:00Z")]);
+    }
+
+    #[test]
+    fn test_weekly_contains() {
+        let course = course();
+        assert!(course.contains(&instant("2024-09-03T18:00:00Z")));
+        assert!(!course.contains(&instant("2024-09-04T18:00:00Z")));
+        assert!(course.contains(&instant("2024-10-10T18:00:00Z")));
+        assert!(!course.contains(&instant("2024-10-15T18:00:00Z")));
+        assert!(!course.contains(&instant("2024-12-17T19:00:00Z")));
+    }
+
+    #[test]
+    fn test_next_transition() {
+        let course = course();
+        assert_eq!(course.next_transition(instant("2024-09-01T00:00:00Z")), Some(instant("2024-09-03T17:00:00Z")));
+        assert_eq!(course.next_transition(instant("2024-09-03T17:00:00Z")), Some(instant("2024-09-03T20:00:00Z")));
+        assert_eq!(course.next_transition(instant("2024-09-03T20:00:00Z")), Some(instant("2024-09-05T17:00:00Z")));
+        assert_eq!(course.next_transition(instant("2024-12-06T00:00:00Z")), None);
+    }
+
+    #[test]
+    fn test_daily_interval_until() {
+        let recurrence = Recurrence {
+            first: TimeRange {
+                from: "2024-01-01".parse().unwrap(),
+                to: "2024-01-01".parse().unwrap(),
+                timezone: Tz::UTC,
+                grace: Grace::default(),
+                roles: Vec::new(),
+            },
+            frequency: Frequency::Daily,
+            interval: 2,
+            weekdays: vec![],
+            count: None,
+            until: NaiveDate::from_ymd_opt(2024, 1, 7),
+            except: vec![],
+        };
+        let days: Vec<String> = recurrence.occurrences().map(|occurrence| occurrence.from.to_string()).collect();
+        assert_eq!(days, vec!["2024-01-01", "2024-01-03", "2024-01-05", "2024-01-07"]);
+        assert!(recurrence.contains(&instant("2024-01-03T12:00:00Z")));
+        assert!(!recurrence.contains(&instant("2024-01-04T12:00:00Z")));
+    }
+
+    #[test]
+    fn test_infinite_recurrence() {
+        let recurrence = Recurrence {
+            first: TimeRange {
+                from: "2024-01-01T09:00".parse().unwrap(),
+                to: "2024-01-01T10:00".parse().unwrap(),
+                timezone: Tz::UTC,
+                grace: Grace::default(),
+                roles: Vec::new(),
+            },
+            frequency: Frequency::Weekly,
+            interval: 2,
+            weekdays: vec![],
+            count: None,
+            until: None,
+            except: vec![],
+        };
+        assert!(recurrence.contains(&instant("2024-01-15T09:30:00Z")));
+        assert!(!recurrence.contains(&instant("2024-01-08T09:30:00Z")));
+        assert_eq!(recurrence.next_transition(instant("2024-01-02T00:00:00Z")), Some(instant("2024-01-15T09:00:00Z")));
+    }
+
+    #[test]
+    fn test_frequency_from_str() {
+        assert_eq!("weekly".parse::<Frequency>().unwrap(), Frequency::Weekly);
+        assert!("yearly".parse::<Frequency>().is_err());
+    }
+}
@@ -0,0 +1,712 @@
+//! # Validation Module
+//!
+//! Semantic checks of a configuration file, run before it is used. Every problem is
+//! reported at once, with its TOML line and column and a hint on how to fix it.
+//!
+//! Errors make the configuration unusable: dangling groups, invalid windows, windows
+//! ending before they start, undeclared roles. Warnings point at likely mistakes that do not prevent
+//! reconciliation: dangling schedules, overlapping windows, duplicate emails and
+//! windows entirely in the past.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{self, Display};
+use std::ops::Range;
+use toml_edit::{ImDocument, Item};
+use crate::config::schedule::{Schedule, ScheduleItem};
+use crate::config::{DEFAULT_ROLE, MissingUserPolicy, ReconcileMode, ScheduleDefaults, parse_galaxy_settings, parse_granted_roles, parse_instance, parse_instance_name, parse_instances, parse_roles, parse_schedule_defaults, parse_schedule_item};
+use crate::galaxy::types::{Email, RoleName};
+
+/// How far after now recurring windows are expanded to look for overlaps.
+const OVERLAP_HORIZON: TimeDelta = TimeDelta::days(366);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Line of the problem, starting at 1.
+    pub line: usize,
+    /// Column of the problem, starting at 1.
+    pub column: usize,
+    pub message: String,
+    /// How to fix the problem.
+    pub hint: Option<String>,
+}
+
+impl Display for Diagnostic {
+    /// Formats the diagnostic as `line:column: severity: message`, followed by the hint.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}: {}", self.line, self.column, self.severity, self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n    hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error returned when a configuration file has errors.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationError {
+    pub fn errors(&self) -> impl Iterator<Item=&Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+}
+
+impl Display for ValidationError {
+    /// Formats the errors, one per line. Warnings are left out.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.errors().map(Diagnostic::to_string).collect();
+        write!(f, "{}", errors.join("\n"))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks a configuration file.
+///
+/// # Arguments
+///
+/// * `source` - The content of the configuration file.
+/// * `now` - The instant windows are considered past before.
+///
+/// # Returns
+///
+/// The problems found, sorted by position.
+pub fn validate(source: &str, now: DateTime<Utc>) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        source,
+        now,
+        declared_roles: BTreeSet::new(),
+        declared_instances: BTreeSet::new(),
+        diagnostics: Vec::new(),
+    };
+    validator.validate();
+    validator.diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column, diagnostic.severity));
+    // Windows inheriting an undeclared role from their group all report it at the same place.
+    validator.diagnostics.dedup();
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    source: &'a str,
+    now: DateTime<Utc>,
+    declared_roles: BTreeSet<RoleName>,
+    declared_instances: BTreeSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A window parsed successfully, with the span of its definition.
+struct Window {
+    schedule_item: ScheduleItem,
+    span: Option<Range<usize>>,
+}
+
+impl Validator<'_> {
+    fn validate(&mut self) {
+        let config: toml::Table = match toml::from_str(self.source) {
+            Ok(config) => config,
+            Err(e) => return self.report(Severity::Error, e.span(), e.message().trim().to_string(), None),
+        };
+        let document = match ImDocument::parse(self.source) {
+            Ok(document) => document,
+            Err(e) => return self.report(Severity::Error, e.span(), e.message().trim().to_string(), None),
+        };
+        let defaults = self.check_defaults(&config, document.as_item(), &ScheduleDefaults::default());
+        let root = document.as_item();
+        self.check_settings(&config, root);
+        self.check_galaxy(&config, child(root, "galaxy"));
+        self.check_roles(&config, child(root, "roles"));
+
+        let Some(groups) = config.get("groups").and_then(toml::Value::as_table) else {
+            return self.report(Severity::Error, None, "groups not found".to_string(), Some("add a [groups] table mapping group names to member emails".to_string()));
+        };
+        let Some(schedule) = config.get("schedule").and_then(toml::Value::as_table) else {
+            return self.report(Severity::Error, None, "schedule not found".to_string(), Some("add a [schedule] table mapping group names to windows".to_string()));
+        };
+        let groups_item = &root["groups"];
+        let schedule_item = &root["schedule"];
+
+        for (group_name, emails) in groups.iter() {
+            let group_item = &groups_item[group_name.as_str()];
+            if !schedule.contains_key(group_name) {
+                self.report(Severity::Error, key_span(groups_item, group_name), format!("group {} has no schedule", group_name),
+                    Some(format!("add `{} = [{{ from = ..., to = ... }}]` to [schedule]", group_name)));
+            }
+            self.check_emails(group_name, emails, group_item);
+        }
+
+        for (group_name, windows) in schedule.iter() {
+            let group_item = &schedule_item[group_name.as_str()];
+            if !groups.contains_key(group_name) {
+                self.report(Severity::Warning, key_span(schedule_item, group_name), format!("schedule of group {}, which has no members", group_name),
+                    Some(format!("add `{}` to [groups] or remove its schedule", group_name)));
+            }
+            let windows = self.check_windows(group_name, windows, group_item, &defaults);
+            self.check_overlaps(&windows);
+        }
+    }
+
+    fn check_emails(&mut self, group_name: &str, emails: &toml::Value, group_item: &Item) {
+        let Some(emails) = emails.as_array() else {
+            return self.report(Severity::Error, group_item.span(), format!("members of group {} must be a list of emails", group_name),
+                Some(format!("write `{} = [\"alice@example.com\"]`", group_name)));
+        };
+        let mut seen = HashSet::new();
+        for (index, email) in emails.iter().enumerate() {
+            let span = group_item[index].span();
+            let Some(email) = email.as_str() else {
+                self.report(Severity::Error, span.clone(), "email must be a string".to_string(), Some("quote the email".to_string()));
+                continue;
+            };
+            if email.parse::<Email>().is_err() {
+                self.report(Severity::Error, span.clone(), format!("invalid email {}", email), None);
+            } else if !seen.insert(email.to_lowercase()) {
+                self.report(Severity::Warning, span, format!("{} is listed twice in group {}", email, group_name), Some("remove the duplicate".to_string()));
+            }
+        }
+    }
+
+    /// Checks the `missing_users`, `reconcile` and `state` settings.
+    fn check_settings(&mut self, config: &toml::Table, root: &Item) {
+        if let Some(missing_users) = config.get("missing_users") {
+            if missing_users.as_str().is_none_or(|policy| policy.parse::<MissingUserPolicy>().is_err()) {
+                self.report(Severity::Error, root["missing_users"].span(), "missing_users must be skip, fail or create".to_string(), None);
+            }
+        }
+        if let Some(reconcile) = config.get("reconcile") {
+            if reconcile.as_str().is_none_or(|mode| mode.parse::<ReconcileMode>().is_err()) {
+                self.report(Severity::Error, root["reconcile"].span(), "reconcile must be replace or preserve".to_string(), None);
+            }
+        }
+        if config.get("state").is_some_and(|state| !state.is_str()) {
+            self.report(Severity::Error, root["state"].span(), "state must be a path".to_string(), Some("give the path of the state file, such as \"training.state.json\"".to_string()));
+        }
+    }
+
+    /// Checks the `[galaxy]` section, either the settings of a single Galaxy or named
+    /// instances, and records the declared instances.
+    fn check_galaxy(&mut self, config: &toml::Table, galaxy_item: &Item) {
+        let Some(galaxy) = config.get("galaxy") else {
+            return;
+        };
+        let Some(galaxy) = galaxy.as_table() else {
+            return self.report(Severity::Error, galaxy_item.span(), "galaxy must be a table".to_string(),
+                Some("set the Galaxy url and API key under [galaxy]".to_string()));
+        };
+        if !galaxy.values().any(toml::Value::is_table) {
+            self.check_galaxy_settings(galaxy, galaxy_item);
+            return;
+        }
+        for (name, instance) in galaxy.iter() {
+            let instance_item = &galaxy_item[name.as_str()];
+            let Some(instance) = instance.as_table() else {
+                self.report(Severity::Error, instance_item.span(), format!("instance {} must be a table", name),
+                    Some("with named instances, set the url and API key of each one in its [galaxy.<name>] section".to_string()));
+                continue;
+            };
+            if let Err(e) = parse_instance_name(name) {
+                self.report(Severity::Error, key_span(galaxy_item, name), format!("{:#}", e), None);
+            }
+            self.declared_instances.insert(name.clone());
+            if self.check_galaxy_settings(instance, instance_item) {
+                if let Err(e) = parse_instance(name, instance) {
+                    self.report(Severity::Error, key_span(galaxy_item, name), format!("{:#}", e),
+                        Some("set its url and one of api_key_file, api_key_command or api_key".to_string()));
+                }
+            }
+        }
+    }
+
+    /// Checks the settings of a Galaxy one by one, and warns about an API key written in
+    /// the configuration. Returns `true` if they are valid.
+    fn check_galaxy_settings(&mut self, galaxy: &toml::Table, galaxy_item: &Item) -> bool {
+        let mut valid = true;
+        for (key, value) in galaxy.iter() {
+            let single = toml::Table::from_iter([(key.clone(), value.clone())]);
+            if let Err(e) = parse_galaxy_settings(&single) {
+                self.report(Severity::Error, galaxy_item[key.as_str()].span(), format!("{:#}", e), None);
+                valid = false;
+            }
+        }
+        if valid {
+            if let Err(e) = parse_galaxy_settings(galaxy) {
+                self.report(Severity::Error, galaxy_item.span(), format!("{:#}", e), Some("keep the source of the API key you use and remove the others".to_string()));
+                valid = false;
+            }
+        }
+        if galaxy.contains_key("api_key") {
+            self.report(Severity::Warning, galaxy_item["api_key"].span(), "the API key is written in the configuration".to_string(),
+                Some("read it from a file readable by you only with api_key_file, or from a credential helper with api_key_command".to_string()));
+        }
+        valid
+    }
+
+    /// Checks that the instances a group is reconciled on are declared.
+    fn check_group_instances(&mut self, group_schedule: &toml::Table, group_item: &Item) {
+        let Some(instances) = group_schedule.get("instances") else {
+            return;
+        };
+        if let Err(e) = parse_instances(instances, |name| self.declared_instances.contains(name)) {
+            self.report(Severity::Error, group_item["instances"].span(), format!("{:#}", e),
+                Some("list instances declared as [galaxy.<name>] sections, such as `instances = [\"main\"]`".to_string()));
+        }
+    }
+
+    /// Checks the role declarations one by one and records the declared roles.
+    fn check_roles(&mut self, config: &toml::Table, roles_item: &Item) {
+        let Some(roles) = config.get("roles") else {
+            self.declared_roles.insert(DEFAULT_ROLE.parse().expect("valid role name"));
+            return;
+        };
+        let Some(roles) = roles.as_table() else {
+            return self.report(Severity::Error, roles_item.span(), "roles must be a table".to_string(),
+                Some("declare each role under [roles], such as `gpu-training = \"Training on GPU nodes\"`".to_string()));
+        };
+        for (role_name, role) in roles.iter() {
+            let single = toml::Table::from_iter([(role_name.clone(), role.clone())]);
+            match parse_roles(&single) {
+                Ok(role) => self.declared_roles.extend(role.into_keys()),
+                Err(e) => self.report(Severity::Error, roles_item[role_name.as_str()].span(), format!("{:#}", e), Some("give the role a description".to_string())),
+            }
+        }
+    }
+
+    /// Reports the roles of `roles` that are not declared.
+    fn check_declared(&mut self, roles: &[RoleName], span: Option<Range<usize>>) {
+        let undeclared: Vec<&RoleName> = roles.iter().filter(|role| !self.declared_roles.contains(role)).collect();
+        for role in undeclared {
+            self.report(Severity::Error, span.clone(), format!("role {} is not declared", role), Some(format!("declare it under [roles], such as `{} = \"description\"`", role)));
+        }
+    }
+
+    /// Checks the `timezone`, `lead` and `lag` keys of a table one by one, so errors point at the faulty key.
+    fn check_defaults(&mut self, table: &toml::Table, item: &Item, defaults: &ScheduleDefaults) -> ScheduleDefaults {
+        for key in ["timezone", "lead", "lag"] {
+            if let Some(value) = table.get(key) {
+                let single = toml::Table::from_iter([(key.to_string(), value.clone())]);
+                if let Err(e) = parse_schedule_defaults(&single, defaults) {
+                    let hint = match key {
+                        "timezone" => "use an IANA timezone name, such as \"America/Montreal\"",
+                        _ => "use a duration such as \"30m\", \"2h\" or \"1d\"",
+                    };
+                    self.report(Severity::Error, item[key].span(), format!("{:#}", e), Some(hint.to_string()));
+                }
+            }
+        }
+        parse_schedule_defaults(table, defaults).unwrap_or(defaults.clone())
+    }
+
+    fn check_windows(&mut self, group_name: &str, windows: &toml::Value, group_item: &Item, defaults: &ScheduleDefaults) -> Vec<Window> {
+        // Where to report undeclared roles inherited by the windows.
+        let mut roles_span = None;
+        let (windows, windows_item, defaults) = match windows {
+            toml::Value::Array(windows) => (windows.as_slice(), group_item, defaults.clone()),
+            toml::Value::Table(group_schedule) => {
+                let mut defaults = self.check_defaults(group_schedule, group_item, defaults);
+                match parse_granted_roles(group_schedule, &defaults.roles) {
+                    Ok(roles) => defaults.roles = roles,
+                    Err(e) => self.report(Severity::Error, group_item["roles"].span(), format!("{:#}", e), Some("write `roles = [\"training\"]`".to_string())),
+                }
+                if group_schedule.contains_key("roles") || group_schedule.contains_key("calendar") {
+                    roles_span = child(group_item, "roles").span().or(group_item.span());
+                    self.check_declared(&defaults.roles, roles_span.clone());
+                }
+                self.check_group_instances(group_schedule, group_item);
+                if group_schedule.get("calendar").is_some_and(|calendar| !calendar.is_str()) {
+                    self.report(Severity::Error, group_item["calendar"].span(), "calendar must be a string".to_string(), Some("give the path or URL of an .ics file".to_string()));
+                }
+                match group_schedule.get("windows") {
+                    Some(toml::Value::Array(windows)) => (windows.as_slice(), &group_item["windows"], defaults),
+                    Some(_) => {
+                        self.report(Severity::Error, group_item["windows"].span(), "windows must be a list".to_string(), None);
+                        return Vec::new();
+                    },
+                    None if group_schedule.contains_key("calendar") => return Vec::new(),
+                    None => {
+                        self.report(Severity::Error, group_item.span(), format!("schedule of group {} has no windows", group_name),
+                            Some("add a `windows` list or a `calendar`".to_string()));
+                        return Vec::new();
+                    },
+                }
+            },
+            _ => {
+                self.report(Severity::Error, group_item.span(), format!("schedule of group {} must be a list of windows or a table", group_name),
+                    Some(format!("write `{} = [{{ from = \"2024-05-01\", to = \"2024-05-03\" }}]`", group_name)));
+                return Vec::new();
+            },
+        };
+
+        let mut parsed = Vec::new();
+        for (index, window) in windows.iter().enumerate() {
+            let span = windows_item[index].span();
+            let schedule_item = match parse_schedule_item(window, &defaults) {
+                Ok(schedule_item) => schedule_item,
+                Err(e) => {
+                    self.report(Severity::Error, span.clone(), format!("invalid window: {:#}", e), Some("a window is `{ from = \"2024-05-01\", to = \"2024-05-03\" }`".to_string()));
+                    continue;
+                },
+            };
+            if window.get("roles").is_some() {
+                self.check_declared(schedule_item.roles(), windows_item[index]["roles"].span());
+            } else if roles_span.is_none() {
+                self.check_declared(schedule_item.roles(), span.clone());
+            }
+            let first = match &schedule_item {
+                ScheduleItem::Once(time_range) => time_range,
+                ScheduleItem::Recurring(recurrence) => &recurrence.first,
+            };
+            if first.end() <= first.start() {
+                self.report(Severity::Error, span.clone(), format!("window ends before it starts, from {} to {}", first.from, first.to), Some("swap from and to".to_string()));
+                continue;
+            }
+            if schedule_item.next_window(&self.now).is_none() {
+                self.report(Severity::Warning, span.clone(), format!("window of group {} is over", group_name), Some("remove past windows".to_string()));
+            }
+            parsed.push(Window {
+                schedule_item,
+                span,
+            });
+        }
+        parsed
+    }
+
+    /// Warns about windows of a group overlapping each other, each window at most once.
+    ///
+    /// Recurring windows are expanded up to [`OVERLAP_HORIZON`] from now, as open-ended ones never end.
+    /// Occurrences of the same window are not compared with each other.
+    fn check_overlaps(&mut self, windows: &[Window]) {
+        let horizon = self.now + OVERLAP_HORIZON;
+        let mut occurrences: Vec<(usize, DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+        for (index, window) in windows.iter().enumerate() {
+            match &window.schedule_item {
+                ScheduleItem::Once(time_range) => occurrences.push((index, time_range.start(), time_range.end())),
+                ScheduleItem::Recurring(recurrence) => occurrences.extend(recurrence.occurrences()
+                    .take_while(|occurrence| occurrence.start() < horizon)
+                    .map(|occurrence| (index, occurrence.start(), occurrence.end()))),
+            }
+        }
+        occurrences.sort_by_key(|(_, start, _)| *start);
+
+        // The latest end seen so far and its window, and the latest end of any other window,
+        // so that a window is compared with the others even while it holds the latest end.
+        let mut latest: Option<(DateTime<Utc>, usize)> = None;
+        let mut runner_up: Option<(DateTime<Utc>, usize)> = None;
+        let mut reported = HashSet::new();
+        for (index, start, end) in occurrences {
+            let other = [latest, runner_up].into_iter().flatten().find(|(_, other)| *other != index);
+            if let Some((other_end, other)) = other {
+                if start < other_end && reported.insert(index) {
+                    let (line, _) = self.position(windows[other].span.clone());
+                    self.report(Severity::Warning, windows[index].span.clone(), format!("window overlaps the window at line {}", line), Some("merge the two windows".to_string()));
+                }
+            }
+            match latest {
+                Some((latest_end, holder)) if end > latest_end => {
+                    if holder != index {
+                        runner_up = latest;
+                    }
+                    latest = Some((end, index));
+                },
+                Some((_, holder)) if holder != index && runner_up.is_none_or(|(runner_up_end, _)| end > runner_up_end) => runner_up = Some((end, index)),
+                Some(_) => {},
+                None => latest = Some((end, index)),
+            }
+        }
+    }
+
+    fn report(&mut self, severity: Severity, span: Option<Range<usize>>, message: String, hint: Option<String>) {
+        let (line, column) = self.position(span);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            column,
+            message,
+            hint,
+        });
+    }
+
+    /// Converts the start of a byte span to a line and column, `1:1` without a span.
+    fn position(&self, span: Option<Range<usize>>) -> (usize, usize) {
+        let offset = span.map(|span| span.start).unwrap_or(0).min(self.source.len());
+        let before = &self.source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        (line, column)
+    }
+}
+
+/// The item at `key`, or `Item::None` if `item` has no such key.
+fn child<'a>(item: &'a Item, key: &str) -> &'a Item {
+    static NONE: Item = Item::None;
+    item.get(key).unwrap_or(&NONE)
+}
+
+fn key_span(table: &Item, key: &str) -> Option<Range<usize>> {
+    table.as_table_like()?.key(key)?.span()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        "2024-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        validate(source, now()).iter().map(|diagnostic| format!("{}:{}: {}: {}", diagnostic.line, diagnostic.column, diagnostic.severity, diagnostic.message)).collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let source = r#"
+[groups]
+team_a = ["alice@example.com"]
+
+[schedule]
+team_a = [{ from = "2024-05-01", to = "2024-05-03" }]
+"#;
+        assert!(validate(source, now()).is_empty());
+    }
+
+    #[test]
+    fn test_dangling_groups_and_schedules() {
+        let source = r#"
+[groups]
+team_a = ["alice@example.com"]
+
+[schedule]
+team_b = [{ from = "2024-05-01", to = "2024-05-03" }]
+"#;
+        assert_eq!(messages(source), vec![
+            "3:1: error: group team_a has no schedule",
+            "6:1: warning: schedule of group team_b, which has no members",
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_windows() {
+        let source = r#"
+[groups]
+team_a = ["alice@example.com", "bob@example.com", "alice@example.com"]
+
+[schedule]
+team_a = [
+    { from = "2024-05-03", to = "2024-05-01" },
+    { from = "2024-05-01" },
+    { from = "2023-05-01", to = "2023-05-03" },
+    { from = "2024-06-01", to = "2024-06-10" },
+    { from = "2024-06-05", to = "2024-06-15" },
+]
+"#;
+        assert_eq!(messages(source), vec![
+            "3:51: warning: alice@example.com is listed twice in group team_a",
+            "7:5: error: window ends before it starts, from 2024-05-03 to 2024-05-01",
+            "8:5: error: invalid window: to not found",
+            "9:5: warning: window of group team_a is over",
+            "11:5: warning: window overlaps the window at line 10",
+        ]);
+    }
+
+    #[test]
+    fn test_overlaps_beyond_neighbours() {
+        let source = r#"
+[groups]
+team_a = ["alice@example.com"]
+
+[schedule]
+team_a = [
+    { from = "2024-06-01", to = "2024-06-10" },
+    { from = "2024-06-02", to = "2024-06-03" },
+    { from = "2024-06-04", to = "2024-06-05" },
+    { from = "2024-06-20", to = "2024-06-21" },
+]
+"#;
+        assert_eq!(messages(source), vec![
+            "8:5: warning: window overlaps the window at line 7",
+            "9:5: warning: window overlaps the window at line 7",
+        ]);
+    }
+
+    #[test]
+    fn test_recurring_overlaps() {
+        let source = r#"
+[groups]
+team_a = ["alice@example.com"]
+
+[schedule]
+team_a = [
+    { from = "2024-03-04T09:00", to = "2024-03-04T12:00", repeat = { frequency = "weekly" } },
+    { from = "2024-05-06T11:00", to = "2024-05-06T13:00" },
+    { from = "2024-05-07T11:00", to = "2024-05-07T13:00" },
+    { from = "2024-01-01T09:00", to = "2024-01-01T10:00", repeat = { frequency = "daily", count = 3 } },
+    { from = "2024-01-02T09:30", to = "2024-01-02T11:00" },
+]
+"#;
+        assert_eq!(messages(source), vec![
+            "8:5: warning: window overlaps the window at line 7",
+            "11:5: warning: window overlaps the window at line 10",
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let source = r#"
+timezone = "Mars/Olympus_Mons"
+
+[groups]
+team_a = ["not an email"]
+
+[schedule]
+team_a = { lag = "soon", windows = [] }
+"#;
+        let diagnostics = validate(source, now());
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Error));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 12));
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (5, 11));
+        assert_eq!((diagnostics[2].line, diagnostics[2].column), (8, 18));
+        assert!(diagnostics[2].hint.is_some());
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        let source = r#"
+missing_users = "ignore"
+reconcile = 1
+state = true
+
+[groups]
+team_a = ["alice@example.com"]
+
+[schedule]
+team_a = [{ from = "2099-01-01", to = "2099-01-02" }]
+"#;
+        let diagnostics = validate(source, now());
+        assert_eq!(diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(), vec![
+            "2:17: error: missing_users must be skip, fail or create",
+            "3:13: error: reconcile must be replace or preserve",
+            "4:9: error: state must be a path\n    hint: give the path of the state file, such as \"training.state.json\"",
+        ]);
+    }
+
+    #[test]
+    fn test_galaxy_settings() {
+        let source = r#"
+[galaxy]
+url = "ftp://galaxy.example.org"
+api_key = "secret"
+api_key_file = 3
+
+[groups]
+team_a = ["alice@example.com"]
+
+[schedule]
+team_a = [{ from = "2099-01-01", to = "2099-01-02" }]
+"#;
+        assert_eq!(messages(source), vec![
+            "3:7: error: invalid Galaxy URL ftp://galaxy.example.org: the scheme must be http or https",
+            "4:11: warning: the API key is written in the configuration",
+            "5:16: error: api_key_file must be a string",
+        ]);
+        let source = source.replace("ftp:", "https:").replace("api_key_file = 3", "api_key_command = \"pass galaxy\"");
+        assert_eq!(messages(&source), vec![
+            "2:1: error: galaxy can only set one of api_key, api_key_file and api_key_command",
+            "4:11: warning: the API key is written in the configuration",
+        ]);
+    }
+
+    #[test]
+    fn test_instances() {
+        let source = r#"
+[galaxy.main]
+url = "https://galaxy.example.org"
+api_key_file = "main.key"
+
+[galaxy.gpu]
+url = "https://gpu.galaxy.example.org"
+
+[galaxy."gpu node"]
+url = "https://node.galaxy.example.org"
+api_key_command = "pass galaxy/node"
+
+[groups]
+team_a = ["alice@example.com"]
+team_b = ["bob@example.com"]
+
+[schedule]
+team_a = { instances = ["main", "staging"], windows = [{ from = "2099-01-01", to = "2099-01-02" }] }
+team_b = { instances = ["gpu"], windows = [{ from = "2099-01-01", to = "2099-01-02" }] }
+"#;
+        assert_eq!(messages(source), vec![
+            "6:9: error: instance gpu has no API key",
+            "9:9: error: invalid instance name gpu node, expected letters, digits, - and _",
+            "18:24: error: instance staging is not declared",
+        ]);
+        let source = source.replace("[galaxy.main]", "[galaxy]\nurl = \"https://galaxy.example.org\"\n\n[galaxy.main]");
+        assert!(messages(&source).contains(&"3:7: error: instance url must be a table".to_string()));
+    }
+
+    #[test]
+    fn test_undeclared_roles() {
+        let source = r#"
+[roles]
+gpu-training = "Training on GPU nodes"
+cpu-training = { description = "Training on CPU nodes" }
+
+[groups]
+team_a = ["alice@example.com"]
+team_b = ["bob@example.com"]
+
+[schedule]
+team_a = { roles = ["gpu-training", "tpu-training"], windows = [
+    { from = "2024-05-01", to = "2024-05-03" },
+    { from = "2024-06-01", to = "2024-06-03", roles = ["cpu-training"] },
+] }
+team_b = [{ from = "2024-05-01", to = "2024-05-03" }]
+"#;
+        assert_eq!(messages(source), vec![
+            "11:20: error: role tpu-training is not declared",
+            "15:11: error: role training is not declared",
+        ]);
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let diagnostics = validate("[groups]\nteam_a = [", now());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].line, 2);
+    }
+
+    #[test]
+    fn test_diagnostic_display() {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            line: 3,
+            column: 5,
+            message: "window of group team_a is over".to_string(),
+            hint: Some("remove past windows".to_string()),
+        };
+        assert_eq!(diagnostic.to_string(), "3:5: warning: window of group team_a is over\n    hint: remove past windows");
+    }
+}
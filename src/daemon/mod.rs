@@ -0,0 +1,237 @@
+//! # Daemon Module
+//!
+//! Keeps the `TrainingManager` of every Galaxy alive and reconciles them whenever a schedule item
+//! starts or ends, plus a periodic safety resync in case Galaxy was changed by hand.
+//!
+//! The configuration file is watched and reloaded when it changes. A configuration
+//! that fails to parse is ignored and the last good one stays in use. The Galaxy
+//! connections are made once at startup, so a configuration changing the `[galaxy]`
+//! section or its named instances is refused too, until the daemon is restarted.
+//!
+//! Calendars are fetched again on every safety resync, so changes to feeds are
+//! picked up even when the configuration file itself does not change.
+//!
+//! The next wake-up is recomputed from the current `ConfigFile` on every iteration,
+//! so reloading the configuration rearms the timers.
+
+pub mod watch;
+
+use anyhow::Result;
+use chrono::Utc;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use crate::cli::log_error;
+use crate::config::{self, ConfigFile};
+use crate::galaxy::ClientSettings;
+use crate::manager::TrainingManager;
+use watch::ConfigWatcher;
+
+pub struct Daemon {
+    /// One training manager per Galaxy, reconciled one after the other.
+    training_managers: Vec<TrainingManager>,
+    config_path: String,
+    config: ConfigFile,
+    /// Timeouts and TLS settings of the requests fetching calendars on reload.
+    client: ClientSettings,
+    resync_interval: Duration,
+}
+
+impl Daemon {
+    /// Creates a daemon for the configuration file at `config_path`, starting from `config`,
+    /// the configuration it was read into.
+    pub fn new(training_managers: Vec<TrainingManager>, config_path: &str, config: ConfigFile, client: ClientSettings, resync_interval: Duration) -> Self {
+        Daemon {
+            training_managers,
+            config_path: config_path.to_string(),
+            config,
+            client,
+            resync_interval,
+        }
+    }
+
+    /// Runs until SIGTERM or SIGINT is received.
+    pub async fn run(&mut self) -> Result<()> {
+        self.run_until(shutdown_signal()).await
+    }
+
+    /// Runs until `shutdown` completes. A reconciliation in progress is never interrupted.
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        tokio::pin!(shutdown);
+        let mut watcher = ConfigWatcher::new(&self.config_path);
+        self.reconcile().await;
+        let mut next_resync = Instant::now() + self.resync_interval;
+        loop {
+            let next_transition = self.config.next_transition(Utc::now());
+            let mut wake_at = next_resync;
+            if let Some(next_transition) = next_transition {
+                let until_transition = (next_transition - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+                wake_at = wake_at.min(Instant::now() + until_transition);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep_until(wake_at) => {},
+                _ = watcher.changed() => {
+                    watcher.settle().await;
+                    if self.reload().await {
+                        self.reconcile().await;
+                        next_resync = Instant::now() + self.resync_interval;
+                    }
+                    continue;
+                },
+                _ = &mut shutdown => {
+                    info!("shutting down");
+                    return Ok(());
+                },
+            }
+
+            // The wall clock may lag behind the monotonic one, only reconcile once the transition is reached.
+            let transition_reached = next_transition.is_some_and(|next_transition| Utc::now() >= next_transition);
+            let resync_due = Instant::now() >= next_resync;
+            if resync_due && !self.config.calendars.is_empty() {
+                self.reload().await;
+            }
+            if transition_reached || resync_due {
+                self.reconcile().await;
+                next_resync = Instant::now() + self.resync_interval;
+            }
+        }
+    }
+
+    /// Re-reads the configuration file, keeping the current configuration if it is invalid,
+    /// one of its calendars cannot be fetched or it changes the Galaxy instances.
+    ///
+    /// Returns `true` if the new configuration was swapped in.
+    async fn reload(&mut self) -> bool {
+        match config::read_config(&self.config_path, &self.client).await {
+            Ok(config) if config.galaxy != self.config.galaxy || config.instances != self.config.instances => {
+                error!(path = self.config_path, "cannot reload the configuration, keeping the last good one: the Galaxy instances changed, restart the daemon to connect to them");
+                false
+            },
+            Ok(config) => {
+                info!(path = self.config_path, "reloaded the configuration");
+                self.config = config;
+                true
+            },
+            Err(e) => {
+                error!(path = self.config_path, "cannot reload the configuration, keeping the last good one: {:#}", e);
+                false
+            },
+        }
+    }
+
+    /// Applies the configuration to every Galaxy. A Galaxy failing does not stop the others.
+    ///
+    /// The changes are logged as they are applied; users left out of their groups are
+    /// logged after every run, until they register.
+    async fn reconcile(&mut self) {
+        for training_manager in self.training_managers.iter() {
+            let instance = training_manager.instance();
+            match training_manager.apply_config(&self.config).await {
+                Ok(plan) => for missing_user in plan.skipped_users() {
+                    let groups = missing_user.groups.iter().map(ToString::to_string).collect::<Vec<_>>();
+                    warn!(instance, user = %missing_user.email, groups = groups.join(", "), "skipped user without a Galaxy account");
+                },
+                Err(e) => match instance {
+                    Some(instance) => log_error(&e.context(format!("cannot reconcile instance {}", instance))),
+                    None => log_error(&e),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = sigterm.recv() => {},
+                _ = tokio::signal::ctrl_c() => {},
+            }
+        },
+        Err(e) => {
+            warn!("cannot listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        },
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::galaxy::mock::MockGalaxy;
+
+    const CONFIG: &str = r#"
+    [groups]
+    group1 = ["user1@email.com"]
+
+    [schedule]
+    group1 = [{ from = "2000-01-01", to = "3000-01-01" }]
+    "#;
+
+    fn config_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("training-manager-daemon-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_run_until_reconciles_before_shutdown() -> Result<()> {
+        let path = config_file("run", CONFIG);
+        let training_manager = TrainingManager::new(Box::new(MockGalaxy::new()?));
+        let mut daemon = Daemon::new(vec![training_manager], &path, config::read_config(&path, &ClientSettings::default()).await?, ClientSettings::default(), Duration::from_secs(3600));
+        daemon.run_until(async {}).await?;
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(daemon.config.state.as_ref().unwrap())?;
+
+        let plan = daemon.training_managers[0].plan(&daemon.config).await?;
+        assert!(plan.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_last_good_config() -> Result<()> {
+        let path = config_file("reload", CONFIG);
+        let training_manager = TrainingManager::new(Box::new(MockGalaxy::new()?));
+        let mut daemon = Daemon::new(vec![training_manager], &path, config::read_config(&path, &ClientSettings::default()).await?, ClientSettings::default(), Duration::from_secs(3600));
+
+        std::fs::write(&path, "[groups]\ngroup1 = [")?;
+        assert!(!daemon.reload().await);
+        assert_eq!(daemon.config.groups.len(), 1);
+
+        std::fs::write(&path, CONFIG.replace("group1", "group2"))?;
+        assert!(daemon.reload().await);
+        std::fs::remove_file(&path)?;
+        assert!(daemon.config.groups.contains_key(&"group2".parse()?));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reload_refuses_changed_instances() -> Result<()> {
+        let path = config_file("instances", CONFIG);
+        let training_manager = TrainingManager::new(Box::new(MockGalaxy::new()?));
+        let mut daemon = Daemon::new(vec![training_manager], &path, config::read_config(&path, &ClientSettings::default()).await?, ClientSettings::default(), Duration::from_secs(3600));
+
+        let instances = "[galaxy.gpu]\nurl = \"https://gpu.example.org\"\napi_key_command = \"pass gpu\"\n\n[galaxy.cpu]\nurl = \"https://cpu.example.org\"\napi_key_command = \"pass cpu\"\n";
+        std::fs::write(&path, format!("{}{}", instances, CONFIG.replace("group1", "group2")))?;
+        assert!(!daemon.reload().await);
+        assert!(daemon.config.instances.is_empty());
+        assert!(daemon.config.groups.contains_key(&"group1".parse()?));
+
+        // Once restarted on the named instances, changing their table is refused as well.
+        daemon.config = config::read_config(&path, &ClientSettings::default()).await?;
+        std::fs::write(&path, format!("{}{}", instances.replace("cpu.example.org", "cpu2.example.org"), CONFIG))?;
+        assert!(!daemon.reload().await);
+        std::fs::remove_file(&path)?;
+        assert_eq!(daemon.config.instances["cpu"].url.as_deref(), Some("https://cpu.example.org"));
+        Ok(())
+    }
+}
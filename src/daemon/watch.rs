@@ -0,0 +1,131 @@
+//! Configuration file watcher
+//!
+//! Notifies the daemon when the configuration file changes. File system events are
+//! used when available. Otherwise, the file metadata is polled at a fixed interval.
+//!
+//! The parent directory is watched rather than the file itself, because most editors
+//! save by writing a new file and renaming it over the old one.
+
+use anyhow::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+
+/// Delay during which successive events are merged into a single change.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct ConfigWatcher {
+    receiver: mpsc::UnboundedReceiver<()>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl ConfigWatcher {
+    /// Watches `path` with file system events, falling back to polling if they are unavailable.
+    pub fn new(path: &str) -> Self {
+        match ConfigWatcher::notify(path) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::warn!(path, "cannot watch the configuration, polling every {}s instead: {:#}", DEFAULT_POLL_INTERVAL.as_secs(), e);
+                ConfigWatcher::polling(path, DEFAULT_POLL_INTERVAL)
+            },
+        }
+    }
+
+    /// Watches `path` with file system events (inotify on Linux).
+    pub fn notify(path: &str) -> Result<Self> {
+        let path = std::fs::canonicalize(path)?;
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let is_config = event.paths.iter().any(|event_path| event_path.file_name() == path.file_name());
+                if is_config && !matches!(event.kind, EventKind::Access(_)) {
+                    let _ = sender.send(());
+                }
+            }
+        })?;
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        Ok(ConfigWatcher {
+            receiver,
+            _watcher: Some(watcher),
+        })
+    }
+
+    /// Watches `path` by comparing its modification time and size every `interval`.
+    pub fn polling(path: &str, interval: Duration) -> Self {
+        let path = PathBuf::from(path);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut last = fingerprint(&path);
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let current = fingerprint(&path);
+                if current != last {
+                    last = current;
+                    if sender.send(()).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        ConfigWatcher {
+            receiver,
+            _watcher: None,
+        }
+    }
+
+    /// Waits for the next change of the file. Cancel safe, it can be used in `tokio::select!`.
+    pub async fn changed(&mut self) {
+        if self.receiver.recv().await.is_none() {
+            // The watcher is gone, never report a change again.
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Waits for the burst of events that usually follows a change to settle.
+    pub async fn settle(&mut self) {
+        tokio::time::sleep(DEBOUNCE).await;
+        while self.receiver.try_recv().is_ok() {}
+    }
+}
+
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("training-manager-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, "a").unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let path = temp_file("notify");
+        let mut watcher = ConfigWatcher::notify(&path).unwrap();
+        std::fs::write(&path, "ab").unwrap();
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(changed.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_polling() {
+        let path = temp_file("polling");
+        let mut watcher = ConfigWatcher::polling(&path, Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(&path, "ab").unwrap();
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(changed.is_ok());
+    }
+}
@@ -0,0 +1,113 @@
+//! # Capabilities Module
+//!
+//! Galaxy releases differ in how they answer the endpoints used here. The
+//! `Capabilities` of a server are detected once, from `/api/version` and
+//! `/api/configuration`, when connecting to it.
+
+use anyhow::{Result, anyhow, bail};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// The oldest release whose API can set the users and roles of a group.
+pub const MIN_VERSION: Version = Version { major: 23, minor: 0 };
+
+/// A Galaxy release, such as `23.1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.trim().split('.');
+        let mut next = || -> Result<u32> {
+            let part = parts.next().unwrap_or("0");
+            part.parse().map_err(|_| anyhow!("invalid Galaxy version {}", s))
+        };
+        Ok(Version { major: next()?, minor: next()? })
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Releases were named after their month, such as 22.05, until 23.0.
+        if self.major < 23 {
+            write!(f, "{}.{:02}", self.major, self.minor)
+        } else {
+            write!(f, "{}.{}", self.major, self.minor)
+        }
+    }
+}
+
+/// The body of `/api/version`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct VersionResponse {
+    pub version_major: String,
+}
+
+/// The settings of `/api/configuration` that change how the API is used.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ConfigurationResponse {
+    #[serde(default)]
+    pub use_remote_user: bool,
+}
+
+/// How a Galaxy server must be talked to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub version: Version,
+    /// Users log in through a proxy: they are created from their email only, and
+    /// have no password to reset.
+    pub remote_user: bool,
+}
+
+impl Capabilities {
+    /// The capabilities of a Galaxy release, an error if it is too old to be managed.
+    pub fn new(version: Version, remote_user: bool) -> Result<Self> {
+        if version < MIN_VERSION {
+            bail!("Galaxy {} is too old: managing the roles of groups needs Galaxy {} or later", version, MIN_VERSION);
+        }
+        Ok(Capabilities {
+            version,
+            remote_user,
+        })
+    }
+}
+
+impl Default for Capabilities {
+    /// The capabilities assumed without asking the server: the oldest supported
+    /// release, with users logging in with a password.
+    fn default() -> Self {
+        Capabilities {
+            version: MIN_VERSION,
+            remote_user: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        assert_eq!("23.1".parse::<Version>().unwrap(), Version { major: 23, minor: 1 });
+        assert_eq!("24".parse::<Version>().unwrap(), Version { major: 24, minor: 0 });
+        assert_eq!("22.05".parse::<Version>().unwrap().to_string(), "22.05");
+        assert_eq!("23.1".parse::<Version>().unwrap().to_string(), "23.1");
+        assert!("dev".parse::<Version>().is_err());
+        assert!("22.05".parse::<Version>().unwrap() < MIN_VERSION);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert!(!Capabilities::new("23.0".parse().unwrap(), false).unwrap().remote_user);
+        assert!(Capabilities::new("24.1".parse().unwrap(), true).unwrap().remote_user);
+        let error = Capabilities::new("22.05".parse().unwrap(), false).unwrap_err();
+        assert_eq!(error.to_string(), "Galaxy 22.05 is too old: managing the roles of groups needs Galaxy 23.0 or later");
+    }
+}
@@ -1,27 +1,48 @@
 //! Galaxy API client module
 //!
 //! This module provides a client for interacting with the Galaxy API.
+//!
+//! Requests are bounded by the connect and request timeouts of `ClientSettings`.
+//! Idempotent requests (GET, PUT and DELETE) are retried on connection errors,
+//! timeouts, `429 Too Many Requests` and `5xx` responses, with an exponential backoff
+//! with jitter, or after the delay given by a `Retry-After` header. POST requests
+//! create entities and are only retried on `429 Too Many Requests`, which the server
+//! answers without processing the request. A `Retry-After` longer than the maximum
+//! backoff is not waited for: the response is returned instead.
+//!
+//! With a rate limit, every attempt first takes a token from a `RateLimiter`.
+//!
+//! Requests that cannot be sent fail with `GalaxyError::Transport`. Responses are
+//! returned whatever their status, for the repositories to check.
 
+use anyhow::{Context, Result, bail};
+use rand::Rng;
+use reqwest::{Certificate, Method, Response, StatusCode};
+use std::path::Path;
+use std::time::Duration;
 use crate::galaxy::config;
-use std::future::Future;
+use crate::galaxy::error::GalaxyError;
+use crate::galaxy::rate_limit::{RateLimitMetrics, RateLimiter};
 
 /// A client for interacting with the Galaxy API
 ///
 /// # Examples
 ///
 /// ```
-/// use crate::galaxy::config::Config;
+/// use crate::galaxy::config::{ClientSettings, Config};
 /// use crate::galaxy::client::Client;
 ///
 /// let config = Config {
 ///     galaxy_url: "https://api.example.com".to_string(),
 ///     api_key: "your-api-key".to_string(),
+///     client: ClientSettings::default(),
 /// };
-/// let client = Client::new(config);
+/// let client = Client::new(config)?;
 /// ```
 pub struct Client {
     client: reqwest::Client,
     config: config::Config,
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl Client {
@@ -30,11 +51,25 @@ impl Client {
     /// # Arguments
     ///
     /// * `config` - The configuration for the Galaxy API
-    pub fn new(config: config::Config) -> Self {
-        Self {
-            client: reqwest::Client::new(),
+    pub fn new(config: config::Config) -> Result<Self> {
+        let client = http_client(&config.client)?;
+        let rate_limiter = match config.client.requests_per_second {
+            Some(requests_per_second) if !(requests_per_second > 0.0 && requests_per_second.is_finite()) => {
+                bail!("the rate limit must be a positive number of requests per second, not {}", requests_per_second);
+            },
+            Some(requests_per_second) => Some(RateLimiter::new(requests_per_second, config.client.burst)),
+            None => None,
+        };
+        Ok(Self {
+            client,
             config,
-        }
+            rate_limiter,
+        })
+    }
+
+    /// The time spent waiting for the rate limit so far.
+    pub fn rate_limit_metrics(&self) -> RateLimitMetrics {
+        self.rate_limiter.as_ref().map(RateLimiter::metrics).unwrap_or_default()
     }
 
     /// Sends a GET request to the specified endpoint
@@ -46,22 +81,21 @@ impl Client {
     /// # Examples
     ///
     /// ```
-    /// # use crate::galaxy::config::Config;
+    /// # use crate::galaxy::config::{ClientSettings, Config};
     /// # use crate::galaxy::client::Client;
     /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
     /// # let config = Config {
     /// #     galaxy_url: "https://api.example.com".to_string(),
     /// #     api_key: "your-api-key".to_string(),
+    /// #     client: ClientSettings::default(),
     /// # };
-    /// # let client = Client::new(config);
+    /// # let client = Client::new(config)?;
     /// let response = client.get("/users").await?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn get(&self, endpoint: &str) -> impl Future<Output = reqwest::Result<reqwest::Response>> {
-        self.client.get(format!("{}{}", self.config.galaxy_url, endpoint))
-            .header("x-api-key", self.config.api_key.clone())
-            .send()
+    pub async fn get(&self, endpoint: &str) -> Result<Response, GalaxyError> {
+        self.send(Method::GET, endpoint, None).await
     }
 
     /// Sends a POST request to the specified endpoint
@@ -74,15 +108,16 @@ impl Client {
     /// # Examples
     ///
     /// ```
-    /// # use crate::galaxy::config::Config;
+    /// # use crate::galaxy::config::{ClientSettings, Config};
     /// # use crate::galaxy::client::Client;
     /// # use serde_json::json;
     /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
     /// # let config = Config {
     /// #     galaxy_url: "https://api.example.com".to_string(),
     /// #     api_key: "your-api-key".to_string(),
+    /// #     client: ClientSettings::default(),
     /// # };
-    /// # let client = Client::new(config);
+    /// # let client = Client::new(config)?;
     /// let body = json!({
     ///     "name": "John Doe",
     ///     "email": "john@example.com"
@@ -91,11 +126,8 @@ impl Client {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn post(&self, endpoint: &str, body: impl serde::Serialize) -> impl Future<Output = reqwest::Result<reqwest::Response>> {
-        self.client.post(format!("{}{}", self.config.galaxy_url, endpoint))
-            .header("x-api-key", self.config.api_key.clone())
-            .json(&body)
-            .send()
+    pub async fn post(&self, endpoint: &str, body: impl serde::Serialize) -> Result<Response, GalaxyError> {
+        self.send(Method::POST, endpoint, Some(encode(endpoint, body)?)).await
     }
 
     /// Sends a PUT request to the specified endpoint
@@ -108,15 +140,16 @@ impl Client {
     /// # Examples
     ///
     /// ```
-    /// # use crate::galaxy::config::Config;
+    /// # use crate::galaxy::config::{ClientSettings, Config};
     /// # use crate::galaxy::client::Client;
     /// # use serde_json::json;
     /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
     /// # let config = Config {
     /// #     galaxy_url: "https://api.example.com".to_string(),
     /// #     api_key: "your-api-key".to_string(),
+    /// #     client: ClientSettings::default(),
     /// # };
-    /// # let client = Client::new(config);
+    /// # let client = Client::new(config)?;
     /// let body = json!({
     ///     "name": "John Doe Updated",
     ///     "email": "john.updated@example.com"
@@ -125,12 +158,141 @@ impl Client {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn put(&self, endpoint: &str, body: impl serde::Serialize) -> impl Future<Output = reqwest::Result<reqwest::Response>> {
-        self.client.put(format!("{}{}", self.config.galaxy_url, endpoint))
-            .header("x-api-key", self.config.api_key.clone())
-            .json(&body)
-            .send()
+    pub async fn put(&self, endpoint: &str, body: impl serde::Serialize) -> Result<Response, GalaxyError> {
+        self.send(Method::PUT, endpoint, Some(encode(endpoint, body)?)).await
+    }
+
+    /// Sends a DELETE request to the specified endpoint
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The API endpoint to send the request to
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use crate::galaxy::config::{ClientSettings, Config};
+    /// # use crate::galaxy::client::Client;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = Config {
+    /// #     galaxy_url: "https://api.example.com".to_string(),
+    /// #     api_key: "your-api-key".to_string(),
+    /// #     client: ClientSettings::default(),
+    /// # };
+    /// # let client = Client::new(config)?;
+    /// let response = client.delete("/users/1").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn delete(&self, endpoint: &str) -> Result<Response, GalaxyError> {
+        self.send(Method::DELETE, endpoint, None).await
+    }
+
+    /// Sends a request with a JSON body, retrying idempotent requests that fail transiently,
+    /// and any request the server throttled.
+    ///
+    /// The last response is returned once the retries are exhausted, even if it is an
+    /// error response, so callers can report it.
+    async fn send(&self, method: Method, endpoint: &str, body: Option<Vec<u8>>) -> Result<Response, GalaxyError> {
+        let idempotent = method != Method::POST;
+        let max_backoff = self.config.client.max_backoff;
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire().await;
+            }
+            let mut request = self.client.request(method.clone(), self.url(endpoint))
+                .header("x-api-key", self.config.api_key.clone());
+            if let Some(body) = &body {
+                request = request.header(reqwest::header::CONTENT_TYPE, "application/json").body(body.clone());
+            }
+            tracing::trace!(%method, endpoint, "sending Galaxy request");
+            let result = request.send().await;
+            // The delay asked by the server, if any, and whether it throttled the request.
+            let retry = match &result {
+                Ok(response) if is_transient(response.status()) => Some((retry_after(response), response.status() == StatusCode::TOO_MANY_REQUESTS)),
+                Ok(_) => None,
+                Err(e) => (e.is_connect() || e.is_timeout()).then_some((None, false)),
+            };
+            let delay = match retry {
+                Some((retry_after, throttled)) if (idempotent || throttled) && attempt < self.config.client.max_retries => match retry_after {
+                    Some(retry_after) if retry_after > max_backoff => {
+                        tracing::warn!(%method, endpoint, retry_after_secs = retry_after.as_secs_f64(), max_backoff_secs = max_backoff.as_secs_f64(), "not retrying Galaxy request, Retry-After is longer than the maximum backoff");
+                        None
+                    },
+                    Some(retry_after) => Some(retry_after),
+                    None => Some(self.backoff(attempt)),
+                },
+                _ => None,
+            };
+            let Some(delay) = delay else {
+                return result.map_err(|source| GalaxyError::Transport { endpoint: endpoint.to_string(), source });
+            };
+            tracing::warn!(%method, endpoint, attempt = attempt + 1, delay_secs = delay.as_secs_f64(), "retrying Galaxy request");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// The URL of an endpoint, under the path of the Galaxy URL if it has one.
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.config.galaxy_url.trim_end_matches('/'), endpoint.trim_start_matches('/'))
     }
+
+    /// The delay before retry number `attempt`, starting at 0: the initial backoff doubled
+    /// on every attempt, of which a random half is kept, so clients retrying together spread out.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let settings = &self.config.client;
+        let backoff = settings.initial_backoff.saturating_mul(2u32.saturating_pow(attempt)).min(settings.max_backoff);
+        backoff / 2 + backoff.mul_f64(rand::rng().random_range(0.0..0.5))
+    }
+}
+
+/// Builds an HTTP client with the timeouts and TLS settings of `settings`, without
+/// retries nor rate limit.
+pub fn http_client(settings: &config::ClientSettings) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(settings.connect_timeout)
+        .timeout(settings.request_timeout);
+    if let Some(ca_bundle) = &settings.ca_bundle {
+        for certificate in read_ca_bundle(ca_bundle)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if settings.insecure {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder.build()?)
+}
+
+/// Reads the certificates of a PEM file.
+fn read_ca_bundle(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path).with_context(|| format!("cannot read CA bundle {}", path.display()))?;
+    let certificates = Certificate::from_pem_bundle(&pem).with_context(|| format!("invalid CA bundle {}", path.display()))?;
+    if certificates.is_empty() {
+        bail!("no certificate in CA bundle {}", path.display());
+    }
+    Ok(certificates)
+}
+
+/// Serializes a request body as JSON.
+fn encode(endpoint: &str, body: impl serde::Serialize) -> Result<Vec<u8>, GalaxyError> {
+    serde_json::to_vec(&body).map_err(|source| GalaxyError::Encode { endpoint: endpoint.to_string(), source })
+}
+
+/// Returns `true` for the statuses worth retrying: rate limiting and server errors.
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// The delay requested by the `Retry-After` header of a response, in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.to_utc() - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO))
 }
 
 #[cfg(test)]
@@ -144,8 +306,12 @@ mod tests {
         let config = config::Config {
             galaxy_url: server.url(),
             api_key: "test-api-key".to_string(),
+            client: config::ClientSettings {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
         };
-        let client = Client::new(config);
+        let client = Client::new(config).unwrap();
         let runtime = Runtime::new().unwrap();
         (server, client, runtime)
     }
@@ -210,4 +376,253 @@ mod tests {
 
         mock.assert();
     }
+
+    #[test]
+    fn test_delete() {
+        let (mut server, client, runtime) = setup_test();
+        let mock = server.mock("DELETE", "/users/1")
+            .match_header("x-api-key", "test-api-key")
+            .with_status(200)
+            .create();
+
+        runtime.block_on(async {
+            let response = client.delete("/users/1").await.unwrap();
+            assert_eq!(response.status(), 200);
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_retry_server_errors() {
+        let (mut server, client, runtime) = setup_test();
+        let failing = server.mock("GET", "/users")
+            .with_status(502)
+            .expect(2)
+            .create();
+        let succeeding = server.mock("GET", "/users")
+            .with_status(200)
+            .expect(1)
+            .create();
+
+        runtime.block_on(async {
+            let response = client.get("/users").await.unwrap();
+            assert_eq!(response.status(), 200);
+        });
+
+        failing.assert();
+        succeeding.assert();
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let (mut server, client, runtime) = setup_test();
+        let limited = server.mock("PUT", "/users/1")
+            .with_status(429)
+            .with_header("retry-after", "1")
+            .expect(1)
+            .create();
+        let succeeding = server.mock("PUT", "/users/1")
+            .with_status(200)
+            .expect(1)
+            .create();
+
+        runtime.block_on(async {
+            let start = std::time::Instant::now();
+            let response = client.put("/users/1", json!({})).await.unwrap();
+            assert_eq!(response.status(), 200);
+            assert!(start.elapsed() >= Duration::from_secs(1));
+        });
+
+        limited.assert();
+        succeeding.assert();
+    }
+
+    #[test]
+    fn test_retry_after_longer_than_max_backoff() {
+        let (mut server, client, runtime) = setup_test();
+        let limited = server.mock("GET", "/users")
+            .with_status(429)
+            .with_header("retry-after", "120")
+            .expect(1)
+            .create();
+
+        runtime.block_on(async {
+            let start = std::time::Instant::now();
+            let response = client.get("/users").await.unwrap();
+            assert_eq!(response.status(), 429);
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+
+        limited.assert();
+    }
+
+    #[test]
+    fn test_post_retried_when_throttled() {
+        let (mut server, client, runtime) = setup_test();
+        let limited = server.mock("POST", "/users")
+            .with_status(429)
+            .expect(1)
+            .create();
+        let succeeding = server.mock("POST", "/users")
+            .with_status(201)
+            .expect(1)
+            .create();
+
+        runtime.block_on(async {
+            let response = client.post("/users", json!({})).await.unwrap();
+            assert_eq!(response.status(), 201);
+        });
+
+        limited.assert();
+        succeeding.assert();
+    }
+
+    #[test]
+    fn test_retries_exhausted() {
+        let (mut server, client, runtime) = setup_test();
+        let mock = server.mock("DELETE", "/users/1")
+            .with_status(503)
+            .expect(4)
+            .create();
+
+        runtime.block_on(async {
+            let response = client.delete("/users/1").await.unwrap();
+            assert_eq!(response.status(), 503);
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_post_not_retried() {
+        let (mut server, client, runtime) = setup_test();
+        let mock = server.mock("POST", "/users")
+            .with_status(502)
+            .expect(1)
+            .create();
+
+        runtime.block_on(async {
+            let response = client.post("/users", json!({})).await.unwrap();
+            assert_eq!(response.status(), 502);
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_retry_connection_errors() {
+        let config = config::Config {
+            // Nothing listens on the discard port.
+            galaxy_url: "http://127.0.0.1:9".to_string(),
+            api_key: "test-api-key".to_string(),
+            client: config::ClientSettings {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+        };
+        let client = Client::new(config).unwrap();
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let error = client.get("/users").await.unwrap_err();
+            assert!(matches!(error, GalaxyError::Transport { ref endpoint, .. } if endpoint == "/users"));
+        });
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut server = mockito::Server::new();
+        let config = config::Config {
+            galaxy_url: server.url(),
+            api_key: "test-api-key".to_string(),
+            client: config::ClientSettings {
+                requests_per_second: Some(20.0),
+                burst: 2,
+                ..Default::default()
+            },
+        };
+        let client = Client::new(config).unwrap();
+        let mock = server.mock("GET", "/users")
+            .with_status(200)
+            .expect(4)
+            .create();
+
+        Runtime::new().unwrap().block_on(async {
+            let start = std::time::Instant::now();
+            for _ in 0..4 {
+                client.get("/users").await.unwrap();
+            }
+            // Two requests fit in the burst, the other two wait 50ms each.
+            assert!(start.elapsed() >= Duration::from_millis(90));
+        });
+
+        mock.assert();
+        assert_eq!(client.rate_limit_metrics().throttled_requests, 2);
+    }
+
+    #[test]
+    fn test_invalid_rate_limit() {
+        let config = config::Config {
+            galaxy_url: "http://localhost".to_string(),
+            api_key: "test-api-key".to_string(),
+            client: config::ClientSettings {
+                requests_per_second: Some(0.0),
+                ..Default::default()
+            },
+        };
+        assert!(Client::new(config).is_err());
+    }
+
+    #[test]
+    fn test_base_path() {
+        let mut server = mockito::Server::new();
+        let mock = server.mock("GET", "/galaxy/api/users")
+            .with_status(200)
+            .expect(2)
+            .create();
+        let config = config::Config {
+            galaxy_url: format!("{}/galaxy/", server.url()),
+            api_key: "test-api-key".to_string(),
+            client: config::ClientSettings::default(),
+        };
+        let client = Client::new(config).unwrap();
+        assert_eq!(client.url("api/users"), format!("{}/galaxy/api/users", server.url()));
+
+        Runtime::new().unwrap().block_on(async {
+            client.get("/api/users").await.unwrap();
+            client.get("api/users").await.unwrap();
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_ca_bundle() {
+        assert_eq!(read_ca_bundle(Path::new("./src/fixtures/ca.pem")).unwrap().len(), 1);
+        assert!(read_ca_bundle(Path::new("./src/fixtures/workshops.ics")).is_err());
+        assert!(read_ca_bundle(Path::new("./src/fixtures/missing.pem")).is_err());
+        let config = config::Config {
+            galaxy_url: "https://localhost".to_string(),
+            api_key: "test-api-key".to_string(),
+            client: config::ClientSettings {
+                ca_bundle: Some("./src/fixtures/ca.pem".into()),
+                insecure: true,
+                ..Default::default()
+            },
+        };
+        assert!(Client::new(config).is_ok());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let (_server, client, _runtime) = setup_test();
+        for attempt in 0..4 {
+            let backoff = client.backoff(attempt);
+            let base = Duration::from_millis(1) * 2u32.pow(attempt);
+            assert!(backoff >= base / 2 && backoff <= base, "{:?} for attempt {}", backoff, attempt);
+        }
+        let capped = client.backoff(40);
+        assert!(capped >= Duration::from_secs(15) && capped <= Duration::from_secs(30));
+    }
 }
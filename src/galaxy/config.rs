@@ -1,12 +1,15 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
 
 pub const API_KEY_ENV: &str = "GALAXY_ADMIN_API_KEY";
+pub const API_KEY_FILE_ENV: &str = "GALAXY_ADMIN_API_KEY_FILE";
 pub const GALAXY_HOSTNAME_ENV: &str = "GALAXY_HOSTNAME";
+pub const GALAXY_URL_ENV: &str = "GALAXY_URL";
 
 pub trait EnvVarProvider {
     fn get(&self, key: &str) -> std::result::Result<String, std::env::VarError>;
-    fn set(&mut self, key: &str, value: &str);
-    fn remove(&mut self, key: &str);
 }
 
 pub struct RealEnvVarProvider;
@@ -21,14 +24,6 @@ impl EnvVarProvider for RealEnvVarProvider {
     fn get(&self, key: &str) -> std::result::Result<String, std::env::VarError> {
         std::env::var(key)
     }
-
-    fn set(&mut self, key: &str, value: &str) {
-        std::env::set_var(key, value)
-    }
-
-    fn remove(&mut self, key: &str) {
-        std::env::remove_var(key)
-    }
 }
 
 fn get_env_var(name: &str, provider: &dyn EnvVarProvider) -> Result<String> {
@@ -42,27 +37,210 @@ fn get_env_var(name: &str, provider: &dyn EnvVarProvider) -> Result<String> {
     }
 }
 
-fn get_api_key(provider: &dyn EnvVarProvider) -> Result<String> {
-    get_env_var(API_KEY_ENV, provider)
+/// The API key: `$GALAXY_ADMIN_API_KEY`, the file at `$GALAXY_ADMIN_API_KEY_FILE`,
+/// or the key source of the `[galaxy]` section of the configuration.
+fn get_api_key(provider: &dyn EnvVarProvider, settings: &GalaxySettings) -> Result<String> {
+    if let Ok(api_key) = get_env_var(API_KEY_ENV, provider) {
+        return Ok(api_key);
+    }
+    if let Ok(path) = get_env_var(API_KEY_FILE_ENV, provider) {
+        return ApiKeySource::File(PathBuf::from(path)).read();
+    }
+    match &settings.api_key {
+        Some(api_key) => api_key.read(),
+        None => bail!("no Galaxy API key: set --api-key, ${}, ${} or api_key_file in the [galaxy] section of the configuration", API_KEY_ENV, API_KEY_FILE_ENV),
+    }
+}
+
+/// The Galaxy URL: `$GALAXY_URL`, `https://$GALAXY_HOSTNAME`, or the `url` of the
+/// `[galaxy]` section of the configuration.
+pub fn get_galaxy_url(provider: &dyn EnvVarProvider, settings: &GalaxySettings) -> Result<String> {
+    if let Ok(galaxy_url) = get_env_var(GALAXY_URL_ENV, provider) {
+        return parse_galaxy_url(&galaxy_url);
+    }
+    if let Ok(hostname) = get_env_var(GALAXY_HOSTNAME_ENV, provider) {
+        return Ok(format!("https://{}", hostname));
+    }
+    match &settings.url {
+        Some(galaxy_url) => parse_galaxy_url(galaxy_url),
+        None => bail!("no Galaxy URL: set --galaxy-url, ${}, ${} or url in the [galaxy] section of the configuration", GALAXY_URL_ENV, GALAXY_HOSTNAME_ENV),
+    }
+}
+
+/// Checks a Galaxy URL, which may have a path when Galaxy is served under a prefix,
+/// and drops its trailing slashes so endpoints can be appended to it.
+pub fn parse_galaxy_url(galaxy_url: &str) -> Result<String> {
+    let url = reqwest::Url::parse(galaxy_url).with_context(|| format!("invalid Galaxy URL {}", galaxy_url))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        bail!("invalid Galaxy URL {}: the scheme must be http or https", galaxy_url);
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        bail!("invalid Galaxy URL {}: it cannot have a query or a fragment", galaxy_url);
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+/// Where the API key is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeySource {
+    /// The key itself.
+    Value(String),
+    /// A file holding the key, such as a Docker or Kubernetes secret. It must not be
+    /// readable by everyone.
+    File(PathBuf),
+    /// A shell command printing the key on its standard output, such as a password manager.
+    Command(String),
+}
+
+impl ApiKeySource {
+    /// Reads the key, without the surrounding whitespace.
+    pub fn read(&self) -> Result<String> {
+        let api_key = match self {
+            ApiKeySource::Value(api_key) => api_key.clone(),
+            ApiKeySource::File(path) => read_api_key_file(path)?,
+            ApiKeySource::Command(command) => run_api_key_command(command)?,
+        };
+        let api_key = api_key.trim();
+        if api_key.is_empty() {
+            bail!("the API key from {} is empty", self);
+        }
+        Ok(api_key.to_string())
+    }
+}
+
+impl std::fmt::Display for ApiKeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeySource::Value(_) => write!(f, "the configuration"),
+            ApiKeySource::File(path) => write!(f, "{}", path.display()),
+            ApiKeySource::Command(command) => write!(f, "`{}`", command),
+        }
+    }
+}
+
+fn read_api_key_file(path: &Path) -> Result<String> {
+    check_api_key_file_permissions(path)?;
+    std::fs::read_to_string(path).with_context(|| format!("cannot read the API key file {}", path.display()))
+}
+
+/// Refuses a key file that every user of the machine can read.
+#[cfg(unix)]
+fn check_api_key_file_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = std::fs::metadata(path).with_context(|| format!("cannot read the API key file {}", path.display()))?;
+    if metadata.permissions().mode() & 0o004 != 0 {
+        bail!("the API key file {} is readable by everyone, restrict it with `chmod o-r {}`", path.display(), path.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_api_key_file_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Runs a credential helper with `sh -c`. Its standard input and error are those of
+/// the training manager, so it can prompt for a passphrase or report why it failed.
+fn run_api_key_command(command: &str) -> Result<String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("cannot run the API key command `{}`", command))?;
+    if !output.status.success() {
+        bail!("the API key command `{}` failed: {}", command, output.status);
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("the API key command `{}` did not print text", command))
 }
 
-pub fn get_galaxy_url(provider: &dyn EnvVarProvider) -> Result<String> {
-    let hostname = get_env_var(GALAXY_HOSTNAME_ENV, provider)?;
-    Ok(format!("https://{}", hostname))
+/// The `[galaxy]` section of the configuration file, used for what is set neither on
+/// the command line nor in the environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GalaxySettings {
+    pub url: Option<String>,
+    pub api_key: Option<ApiKeySource>,
 }
 
 pub struct Config {
     pub galaxy_url: String,
     pub api_key: String,
+    pub client: ClientSettings,
+}
+
+/// Timeouts and retries of the requests sent to Galaxy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSettings {
+    /// Maximum time to establish a connection.
+    pub connect_timeout: Duration,
+    /// Maximum time for a whole request, response body included.
+    pub request_timeout: Duration,
+    /// Number of times a failed idempotent request is retried.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two attempts. A longer `Retry-After` is not waited for.
+    pub max_backoff: Duration,
+    /// Maximum sustained number of requests per second, unlimited if `None`.
+    pub requests_per_second: Option<f64>,
+    /// Number of requests sent at once before the rate limit applies.
+    pub burst: u32,
+    /// PEM file of the certificate authorities trusted in addition to the system ones.
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept any TLS certificate, for local testing only.
+    pub insecure: bool,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(60),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            requests_per_second: None,
+            burst: 10,
+            ca_bundle: None,
+            insecure: false,
+        }
+    }
 }
 
 impl Config {
-    pub fn new(provider: &dyn EnvVarProvider) -> Result<Config> {
-        let galaxy_url = get_galaxy_url(provider)?;
-        let api_key = get_api_key(provider)?;
+    /// Builds the configuration, using the given values instead of the environment when
+    /// set, and the `[galaxy]` section of the configuration file for what the
+    /// environment does not set.
+    pub fn with_overrides(provider: &dyn EnvVarProvider, galaxy_url: Option<String>, api_key: Option<String>, settings: &GalaxySettings) -> Result<Config> {
+        let galaxy_url = match galaxy_url {
+            Some(galaxy_url) => parse_galaxy_url(&galaxy_url)?,
+            None => get_galaxy_url(provider, settings)?,
+        };
+        let api_key = match api_key {
+            Some(api_key) => api_key,
+            None => get_api_key(provider, settings)?,
+        };
         Ok(Config {
             galaxy_url,
             api_key,
+            client: ClientSettings::default(),
+        })
+    }
+
+    /// Builds the configuration of a named instance, from its own settings only: the
+    /// environment is shared by every instance, so it cannot give their URL or API key.
+    pub fn for_instance(instance: &str, settings: &GalaxySettings) -> Result<Config> {
+        let Some(galaxy_url) = &settings.url else {
+            bail!("instance {} has no url", instance);
+        };
+        let Some(api_key) = &settings.api_key else {
+            bail!("instance {} has no API key", instance);
+        };
+        Ok(Config {
+            galaxy_url: parse_galaxy_url(galaxy_url)?,
+            api_key: api_key.read()?,
+            client: ClientSettings::default(),
         })
     }
 }
@@ -81,12 +259,6 @@ mod tests {
                 env_vars: std::collections::HashMap::new(),
             }
         }
-    }
-
-    impl EnvVarProvider for MockEnvVarProvider {
-        fn get(&self, key: &str) -> std::result::Result<String, std::env::VarError> {
-            self.env_vars.get(key).map(|v| v.to_string()).ok_or(std::env::VarError::NotPresent)
-        }
 
         fn set(&mut self, key: &str, value: &str) {
             self.env_vars.insert(key.to_string(), value.to_string());
@@ -97,9 +269,15 @@ mod tests {
         }
     }
 
+    impl EnvVarProvider for MockEnvVarProvider {
+        fn get(&self, key: &str) -> std::result::Result<String, std::env::VarError> {
+            self.env_vars.get(key).map(|v| v.to_string()).ok_or(std::env::VarError::NotPresent)
+        }
+    }
+
     #[test]
     fn test_get_env_var() {
-        let mut provider = RealEnvVarProvider::new();
+        let mut provider = MockEnvVarProvider::new();
         let test_key = "test";
         provider.set(test_key, "test");
         assert_eq!(get_env_var("test", &provider).unwrap(), "test");
@@ -114,28 +292,47 @@ mod tests {
     fn test_get_api_key() {
         let mut mock_provider = MockEnvVarProvider::new();
         mock_provider.set(API_KEY_ENV, "test");
-        assert_eq!(get_api_key(&mock_provider).unwrap(), "test");
+        assert_eq!(get_api_key(&mock_provider, &GalaxySettings::default()).unwrap(), "test");
     }
 
     #[test]
     fn test_get_api_key_not_set() {
         let mut mock_provider = MockEnvVarProvider::new();
         mock_provider.remove(API_KEY_ENV);
-        assert!(get_api_key(&mock_provider).is_err());
+        assert!(get_api_key(&mock_provider, &GalaxySettings::default()).is_err());
     }
 
     #[test]
     fn test_get_galaxy_url() {
         let mut mock_provider = MockEnvVarProvider::new();
         mock_provider.set(GALAXY_HOSTNAME_ENV, "test.ca");
-        assert_eq!(get_galaxy_url(&mock_provider).unwrap(), "https://test.ca");
+        assert_eq!(get_galaxy_url(&mock_provider, &GalaxySettings::default()).unwrap(), "https://test.ca");
+    }
+
+    #[test]
+    fn test_get_galaxy_url_with_path() {
+        let mut mock_provider = MockEnvVarProvider::new();
+        mock_provider.set(GALAXY_HOSTNAME_ENV, "test.ca");
+        mock_provider.set(GALAXY_URL_ENV, "http://localhost:8080/galaxy/");
+        assert_eq!(get_galaxy_url(&mock_provider, &GalaxySettings::default()).unwrap(), "http://localhost:8080/galaxy");
+        mock_provider.set(GALAXY_URL_ENV, "ftp://test.ca");
+        assert!(get_galaxy_url(&mock_provider, &GalaxySettings::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_galaxy_url() {
+        assert_eq!(parse_galaxy_url("https://test.ca").unwrap(), "https://test.ca");
+        assert_eq!(parse_galaxy_url("https://test.ca/").unwrap(), "https://test.ca");
+        assert_eq!(parse_galaxy_url("https://test.ca/galaxy//").unwrap(), "https://test.ca/galaxy");
+        assert!(parse_galaxy_url("test.ca").is_err());
+        assert!(parse_galaxy_url("https://test.ca/?a=b").is_err());
     }
 
     #[test]
     fn test_get_galaxy_url_not_set() {
         let mut mock_provider = MockEnvVarProvider::new();
         mock_provider.remove(GALAXY_HOSTNAME_ENV);
-        assert!(get_galaxy_url(&mock_provider).is_err());
+        assert!(get_galaxy_url(&mock_provider, &GalaxySettings::default()).is_err());
     }
 
     #[test]
@@ -143,18 +340,112 @@ mod tests {
         let mut mock_provider = MockEnvVarProvider::new();
         mock_provider.set(API_KEY_ENV, "test");
         mock_provider.set(GALAXY_HOSTNAME_ENV, "test.ca");
-        let config = Config::new(&mock_provider);
+        let config = Config::with_overrides(&mock_provider, None, None, &GalaxySettings::default());
         assert!(config.is_ok());
         let config = config.unwrap();
         assert_eq!(config.galaxy_url, "https://test.ca");
         assert_eq!(config.api_key, "test");
     }
 
+    #[test]
+    fn test_config_with_overrides() {
+        let mut mock_provider = MockEnvVarProvider::new();
+        mock_provider.set(API_KEY_ENV, "test");
+        mock_provider.set(GALAXY_HOSTNAME_ENV, "test.ca");
+        let config = Config::with_overrides(&mock_provider, Some("http://localhost:8080".to_string()), None, &GalaxySettings::default()).unwrap();
+        assert_eq!(config.galaxy_url, "http://localhost:8080");
+        assert_eq!(config.api_key, "test");
+        mock_provider.remove(API_KEY_ENV);
+        mock_provider.remove(GALAXY_HOSTNAME_ENV);
+        let config = Config::with_overrides(&mock_provider, Some("http://localhost:8080".to_string()), Some("key".to_string()), &GalaxySettings::default()).unwrap();
+        assert_eq!(config.api_key, "key");
+    }
+
+    #[test]
+    fn test_config_for_instance() {
+        let settings = GalaxySettings {
+            url: Some("https://gpu.test.ca/".to_string()),
+            api_key: Some(ApiKeySource::Value("gpu".to_string())),
+        };
+        let config = Config::for_instance("gpu", &settings).unwrap();
+        assert_eq!(config.galaxy_url, "https://gpu.test.ca");
+        assert_eq!(config.api_key, "gpu");
+        let error = Config::for_instance("gpu", &GalaxySettings { api_key: None, ..settings }).err().unwrap();
+        assert_eq!(error.to_string(), "instance gpu has no API key");
+    }
+
     #[test]
     fn test_config_new_not_set() {
         let mut mock_provider = MockEnvVarProvider::new();
         mock_provider.remove(API_KEY_ENV);
-        let config = Config::new(&mock_provider);
+        let config = Config::with_overrides(&mock_provider, None, None, &GalaxySettings::default());
         assert!(config.is_err());
     }
+
+    #[cfg(unix)]
+    /// Writes a key file in a fresh directory with the given permissions.
+    fn api_key_file(name: &str, content: &str, mode: u32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("galaxy-training-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("api_key");
+        std::fs::write(&path, content).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_api_key_file() {
+        let path = api_key_file("private", "secret\n", 0o600);
+        assert_eq!(ApiKeySource::File(path.clone()).read().unwrap(), "secret");
+        let mut mock_provider = MockEnvVarProvider::new();
+        mock_provider.set(API_KEY_FILE_ENV, path.to_str().unwrap());
+        assert_eq!(get_api_key(&mock_provider, &GalaxySettings::default()).unwrap(), "secret");
+
+        let path = api_key_file("public", "secret\n", 0o644);
+        let error = ApiKeySource::File(path.clone()).read().unwrap_err();
+        assert_eq!(error.to_string(), format!("the API key file {} is readable by everyone, restrict it with `chmod o-r {}`", path.display(), path.display()));
+
+        let path = api_key_file("empty", "\n", 0o600);
+        assert!(ApiKeySource::File(path).read().is_err());
+        assert!(ApiKeySource::File(PathBuf::from("/nonexistent/api_key")).read().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_api_key_command() {
+        assert_eq!(ApiKeySource::Command("echo secret".to_string()).read().unwrap(), "secret");
+        let error = ApiKeySource::Command("exit 3".to_string()).read().unwrap_err();
+        assert_eq!(error.to_string(), "the API key command `exit 3` failed: exit status: 3");
+        assert!(ApiKeySource::Command("true".to_string()).read().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_precedence() {
+        let settings = GalaxySettings {
+            url: Some("https://config.ca/".to_string()),
+            api_key: Some(ApiKeySource::Value("config".to_string())),
+        };
+        let mut mock_provider = MockEnvVarProvider::new();
+        let config = Config::with_overrides(&mock_provider, None, None, &settings).unwrap();
+        assert_eq!(config.galaxy_url, "https://config.ca");
+        assert_eq!(config.api_key, "config");
+
+        let path = api_key_file("precedence", "file", 0o400);
+        mock_provider.set(API_KEY_FILE_ENV, path.to_str().unwrap());
+        mock_provider.set(GALAXY_HOSTNAME_ENV, "env.ca");
+        let config = Config::with_overrides(&mock_provider, None, None, &settings).unwrap();
+        assert_eq!(config.galaxy_url, "https://env.ca");
+        assert_eq!(config.api_key, "file");
+
+        mock_provider.set(API_KEY_ENV, "env");
+        let config = Config::with_overrides(&mock_provider, None, None, &settings).unwrap();
+        assert_eq!(config.api_key, "env");
+
+        let config = Config::with_overrides(&mock_provider, Some("https://cli.ca".to_string()), Some("cli".to_string()), &settings).unwrap();
+        assert_eq!(config.galaxy_url, "https://cli.ca");
+        assert_eq!(config.api_key, "cli");
+    }
 }
@@ -0,0 +1,172 @@
+//! # Error Module
+//!
+//! Errors returned by the Galaxy API, typed by the status of the response so callers
+//! can tell a bad API key from a missing entity or an unreachable server.
+//!
+//! Galaxy reports errors as `{"err_msg": "...", "err_code": 400008}`; both are kept
+//! along with the raw body. The repositories return them wrapped in `anyhow::Error`,
+//! so callers branch with `downcast_ref::<GalaxyError>()`.
+
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+
+/// An error response from Galaxy.
+#[derive(Debug)]
+pub struct ApiError {
+    pub endpoint: String,
+    pub status: StatusCode,
+    /// The raw body of the response.
+    pub body: String,
+    /// Galaxy's message, when the body is a Galaxy error.
+    pub err_msg: Option<String>,
+    /// Galaxy's error code, when the body is a Galaxy error.
+    pub err_code: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    err_msg: Option<String>,
+    err_code: Option<u64>,
+}
+
+impl ApiError {
+    /// Reads the body of an error response.
+    pub async fn from_response(endpoint: &str, response: Response) -> Self {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let (err_msg, err_code) = match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(error_body) => (error_body.err_msg, error_body.err_code),
+            Err(_) => (None, None),
+        };
+        ApiError {
+            endpoint: endpoint.to_string(),
+            status,
+            body,
+            err_msg,
+            err_code,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} returned {}", self.endpoint, self.status)?;
+        match (&self.err_msg, self.err_code) {
+            (Some(err_msg), Some(err_code)) => write!(f, ": {} (error {})", err_msg, err_code),
+            (Some(err_msg), None) => write!(f, ": {}", err_msg),
+            _ if !self.body.trim().is_empty() => write!(f, ": {}", self.body.trim()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An error of a Galaxy API call.
+#[derive(Debug)]
+pub enum GalaxyError {
+    /// `401`: the API key is missing or invalid.
+    Unauthorized(ApiError),
+    /// `403`: the API key does not belong to an admin.
+    Forbidden(ApiError),
+    /// `404`: the entity does not exist, or the Galaxy URL is wrong.
+    NotFound(ApiError),
+    /// `409`: the entity conflicts with an existing one.
+    Conflict(ApiError),
+    /// `400` or `422`: Galaxy rejected the request.
+    Validation(ApiError),
+    /// Any other unexpected status, server errors included.
+    Unexpected(ApiError),
+    /// The request could not be sent or the response not received.
+    Transport { endpoint: String, source: reqwest::Error },
+    /// The request body could not be serialized.
+    Encode { endpoint: String, source: serde_json::Error },
+    /// The response body is not what the endpoint should return.
+    Decode { endpoint: String, body: String, source: serde_json::Error },
+}
+
+impl GalaxyError {
+    /// Builds the error matching the status of an unexpected response.
+    pub async fn from_response(endpoint: &str, response: Response) -> Self {
+        ApiError::from_response(endpoint, response).await.into()
+    }
+
+    /// What the user can do about the error, if there is something obvious.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            GalaxyError::Unauthorized(_) => Some("check the API key given by --api-key, $GALAXY_ADMIN_API_KEY, $GALAXY_ADMIN_API_KEY_FILE or the [galaxy] section of the configuration"),
+            GalaxyError::Forbidden(_) => Some("the API key must belong to a user listed in admin_users of the Galaxy configuration"),
+            GalaxyError::Transport { .. } => Some("check the Galaxy URL given by --galaxy-url, $GALAXY_URL, $GALAXY_HOSTNAME or the [galaxy] section of the configuration, and that Galaxy is up"),
+            _ => None,
+        }
+    }
+}
+
+impl From<ApiError> for GalaxyError {
+    fn from(error: ApiError) -> Self {
+        match error.status {
+            StatusCode::UNAUTHORIZED => GalaxyError::Unauthorized(error),
+            StatusCode::FORBIDDEN => GalaxyError::Forbidden(error),
+            StatusCode::NOT_FOUND => GalaxyError::NotFound(error),
+            StatusCode::CONFLICT => GalaxyError::Conflict(error),
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => GalaxyError::Validation(error),
+            _ => GalaxyError::Unexpected(error),
+        }
+    }
+}
+
+impl Display for GalaxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GalaxyError::Unauthorized(error) => write!(f, "unauthorized: {}", error),
+            GalaxyError::Forbidden(error) => write!(f, "forbidden: {}", error),
+            GalaxyError::NotFound(error) => write!(f, "not found: {}", error),
+            GalaxyError::Conflict(error) => write!(f, "conflict: {}", error),
+            GalaxyError::Validation(error) => write!(f, "rejected: {}", error),
+            GalaxyError::Unexpected(error) => write!(f, "{}", error),
+            GalaxyError::Transport { endpoint, source } => write!(f, "cannot reach {}: {}", endpoint, source),
+            GalaxyError::Encode { endpoint, source } => write!(f, "cannot encode the request to {}: {}", endpoint, source),
+            GalaxyError::Decode { endpoint, body, source } => write!(f, "unexpected response from {}: {}: {}", endpoint, source, body),
+        }
+    }
+}
+
+impl std::error::Error for GalaxyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GalaxyError::Transport { source, .. } => Some(source),
+            GalaxyError::Encode { source, .. } | GalaxyError::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: StatusCode, body: &str, err_msg: Option<&str>) -> ApiError {
+        ApiError {
+            endpoint: "/api/groups".to_string(),
+            status,
+            body: body.to_string(),
+            err_msg: err_msg.map(str::to_string),
+            err_code: None,
+        }
+    }
+
+    #[test]
+    fn test_from_api_error() {
+        assert!(matches!(GalaxyError::from(api_error(StatusCode::CONFLICT, "", None)), GalaxyError::Conflict(_)));
+        assert!(matches!(GalaxyError::from(api_error(StatusCode::UNPROCESSABLE_ENTITY, "", None)), GalaxyError::Validation(_)));
+        assert!(matches!(GalaxyError::from(api_error(StatusCode::BAD_GATEWAY, "", None)), GalaxyError::Unexpected(_)));
+    }
+
+    #[test]
+    fn test_display() {
+        let error = GalaxyError::from(api_error(StatusCode::INTERNAL_SERVER_ERROR, "", None));
+        assert_eq!(error.to_string(), "/api/groups returned 500 Internal Server Error");
+        let error = GalaxyError::from(api_error(StatusCode::CONFLICT, "{}", Some("Group exists")));
+        assert_eq!(error.to_string(), "conflict: /api/groups returned 409 Conflict: Group exists");
+        assert!(error.hint().is_none());
+    }
+}
@@ -1,24 +1,48 @@
-use crate::galaxy::{GalaxyAPI, GroupRepository, GroupRoleRepository, GroupUserRepository, RoleRepository, UserRepository, Group, Role, User, GroupUpdatePayload};
+use crate::galaxy::{GalaxyAPI, GalaxyError, GroupRepository, GroupRoleRepository, GroupUserRepository, RoleRepository, UserRepository, Group, Role, User, GroupUpdatePayload, UserCreatePayload};
 use anyhow::Result;
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 use async_trait::async_trait;
+use reqwest::StatusCode;
+use crate::galaxy::error::ApiError;
 use crate::galaxy::types::*;
 
 /*
  * MockGalaxy
  */
 
-struct  MockGalaxy {
+/// An in-memory Galaxy. The repositories take `&self`, so its content sits behind a
+/// mutex, never held across an await.
+pub(crate) struct MockGalaxy {
+    state: Mutex<MockState>,
+    /// How long `update_group` takes, to observe concurrent updates.
+    update_delay: Option<Duration>,
+    in_flight: AtomicUsize,
+    /// Highest number of `update_group` calls in progress at the same time.
+    max_in_flight: Arc<AtomicUsize>,
+    /// Number of `find_user` calls.
+    user_lookups: Arc<AtomicUsize>,
+    /// Whether `send_password_reset` fails.
+    failing_password_resets: Arc<AtomicBool>,
+}
+
+struct MockState {
     id_generator: IDGenerator,
     users: HashMap<UserID, User>,
     roles: HashMap<RoleID, Role>,
     groups: HashMap<GroupID, Group>,
     group_roles: HashMap<GroupID, HashSet<RoleID>>,
     group_users: HashMap<GroupID, HashSet<UserID>>,
+    /// Emails a password reset was sent to.
+    password_resets: Vec<Email>,
+    /// Groups whose updates fail.
+    failing_groups: HashSet<GroupID>,
 }
 
 impl MockGalaxy {
-    fn new() -> Result<Self> {
+    pub(crate) fn new() -> Result<Self> {
         let id_generator = IDGenerator::new();
         let users = HashMap::from([
             ("user1".parse()?,
@@ -36,15 +60,80 @@ impl MockGalaxy {
         let group_users = HashMap::from(
             [("group1".parse()?, HashSet::new())]
         );
-        Ok(MockGalaxy {
+        Ok(MockGalaxy::from_state(MockState {
             id_generator,
             users,
             roles,
             groups,
             group_roles,
             group_users,
+            password_resets: Vec::new(),
+            failing_groups: HashSet::new(),
+        }))
+    }
+
+    /// A Galaxy without any user, role or group, as on a first run.
+    pub(crate) fn empty() -> Self {
+        MockGalaxy::from_state(MockState {
+            id_generator: IDGenerator::new(),
+            users: HashMap::new(),
+            roles: HashMap::new(),
+            groups: HashMap::new(),
+            group_roles: HashMap::new(),
+            group_users: HashMap::new(),
+            password_resets: Vec::new(),
+            failing_groups: HashSet::new(),
         })
     }
+
+    fn from_state(state: MockState) -> Self {
+        MockGalaxy {
+            state: Mutex::new(state),
+            update_delay: None,
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: Arc::new(AtomicUsize::new(0)),
+            user_lookups: Arc::new(AtomicUsize::new(0)),
+            failing_password_resets: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock state poisoned")
+    }
+
+    pub(crate) fn add_user(&mut self, id: &str, email: &str) -> Result<()> {
+        self.state().users.insert(id.parse()?, User::new(id, email)?);
+        Ok(())
+    }
+
+    /// Makes every `update_group` call take `delay`, and returns the highest number
+    /// of calls seen in progress at the same time.
+    pub(crate) fn slow_updates(&mut self, delay: Duration) -> Arc<AtomicUsize> {
+        self.update_delay = Some(delay);
+        self.max_in_flight.clone()
+    }
+
+    /// Makes the updates of `group_id` fail.
+    pub(crate) fn fail_updates(&mut self, group_id: &str) -> Result<()> {
+        self.state().failing_groups.insert(group_id.parse()?);
+        Ok(())
+    }
+
+    /// Makes password resets fail, until the returned flag is cleared.
+    pub(crate) fn fail_password_resets(&mut self) -> Arc<AtomicBool> {
+        self.failing_password_resets.store(true, Ordering::SeqCst);
+        self.failing_password_resets.clone()
+    }
+
+    /// Emails a password reset was sent to.
+    pub(crate) fn password_resets(&self) -> Vec<Email> {
+        self.state().password_resets.clone()
+    }
+
+    /// The number of users looked up by email so far.
+    pub(crate) fn user_lookups(&self) -> Arc<AtomicUsize> {
+        self.user_lookups.clone()
+    }
 }
 
 impl GalaxyAPI for MockGalaxy {
@@ -53,60 +142,99 @@ impl GalaxyAPI for MockGalaxy {
 #[async_trait]
 impl GroupRepository for MockGalaxy {
     async fn get_groups(&self) -> Result<Vec<Group>> {
-        Ok(self.groups.values().cloned().collect())
+        let state = self.state();
+        Ok(state.groups.values().cloned().collect())
     }
 
-    async fn create_group(&mut self, name: &str) -> Result<Group> {
-        let id =  self.id_generator.next();
+    async fn create_group(&self, name: &str) -> Result<Group> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        let id = state.id_generator.next();
         let group = Group::new(&id, name)?;
-        self.groups.insert(group.id.clone(), group.clone());
-        self.group_roles.insert(group.id.clone(), HashSet::new());
-        self.group_users.insert(group.id.clone(), HashSet::new());
+        state.groups.insert(group.id.clone(), group.clone());
+        state.group_roles.insert(group.id.clone(), HashSet::new());
+        state.group_users.insert(group.id.clone(), HashSet::new());
         Ok(group)
     }
 
-    async fn update_group(&mut self, group_id: &GroupID, payload: &GroupUpdatePayload) -> Result<Group> {
-        let group = match self.groups.get_mut(group_id) {
+    async fn update_group(&self, group_id: &GroupID, payload: &GroupUpdatePayload) -> Result<Group> {
+        if let Some(delay) = self.update_delay {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(delay).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if state.failing_groups.contains(group_id) {
+            return Err(anyhow::anyhow!("update of group {} failed", group_id));
+        }
+        let group = match state.groups.get_mut(group_id) {
             Some(group) => group,
-            None => return Err(anyhow::anyhow!("group {} does not exist", group_id)),
+            None => return Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}", group_id), format!("group {} does not exist", group_id))),
         };
         if let Some(name) = &payload.name {
             group.name = name.clone();
         }
         if let Some(user_ids) = &payload.user_ids {
-            self.group_users.insert(group_id.clone(), HashSet::from_iter(user_ids.clone()));
+            state.group_users.insert(group_id.clone(), HashSet::from_iter(user_ids.clone()));
         }
         if let Some(role_ids) = &payload.role_ids {
-            self.group_roles.insert(group_id.clone(), HashSet::from_iter(role_ids.clone()));
+            state.group_roles.insert(group_id.clone(), HashSet::from_iter(role_ids.clone()));
         }
         Ok(group.clone())
     }
+
+    async fn delete_group(&self, group_id: &GroupID) -> Result<()> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if state.groups.remove(group_id).is_none() {
+            return Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}", group_id), format!("group {} does not exist", group_id)));
+        }
+        state.group_roles.remove(group_id);
+        state.group_users.remove(group_id);
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl GroupRoleRepository for MockGalaxy {
     async fn get_group_roles(&self, group_id: &GroupID) -> Result<Vec<Role>> {
-        let group_roles = match self.group_roles.get(group_id) {
+        let state = self.state();
+        let group_roles = match state.group_roles.get(group_id) {
             Some(group_roles) => group_roles.clone(),
             None => HashSet::new(),
         };
         Ok(group_roles.iter()
-            .filter_map(|role_id| self.roles.get(role_id))
+            .filter_map(|role_id| state.roles.get(role_id))
             .cloned()
             .collect())
-        
     }
 
-    async fn add_role_to_group(&mut self, role_id: &RoleID, group_id: &GroupID) -> Result<()> {
-        if !self.roles.contains_key(role_id) {
-            return Err(anyhow::anyhow!("role {} does not exist", role_id));
+    async fn add_role_to_group(&self, role_id: &RoleID, group_id: &GroupID) -> Result<()> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if !state.roles.contains_key(role_id) {
+            return Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}/roles/{}", group_id, role_id), format!("role {} does not exist", role_id)));
         }
-        match self.group_roles.get_mut(group_id) {
+        match state.group_roles.get_mut(group_id) {
             Some(group_roles) => {
                 let _ = group_roles.insert(role_id.clone());
                 Ok(())
             },
-            None => Err(anyhow::anyhow!("group {} does not exist", group_id)),
+            None => Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}", group_id), format!("group {} does not exist", group_id))),
+        }
+    }
+
+    async fn remove_role_from_group(&self, role_id: &RoleID, group_id: &GroupID) -> Result<()> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        match state.group_roles.get_mut(group_id) {
+            Some(group_roles) => {
+                let _ = group_roles.remove(role_id);
+                Ok(())
+            },
+            None => Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}", group_id), format!("group {} does not exist", group_id))),
         }
     }
 }
@@ -114,25 +242,40 @@ impl GroupRoleRepository for MockGalaxy {
 #[async_trait]
 impl GroupUserRepository for MockGalaxy {
     async fn get_group_users(&self, group_id: &GroupID) -> Result<Vec<User>> {
-        let group_users = match self.group_users.get(group_id) {
+        let state = self.state();
+        let group_users = match state.group_users.get(group_id) {
             Some(group_users) => group_users.clone(),
             None => HashSet::new(),
         };
         Ok(group_users.iter()
-            .filter_map(|user_id| self.users.get(user_id))
+            .filter_map(|user_id| state.users.get(user_id))
             .cloned()
             .collect())
     }
-    async fn add_user_to_group(&mut self, user_id: &UserID, group_id: &GroupID) -> Result<()> {
-        if !self.users.contains_key(user_id) {
-            return Err(anyhow::anyhow!("user {} does not exist", user_id));
+    async fn add_user_to_group(&self, user_id: &UserID, group_id: &GroupID) -> Result<()> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if !state.users.contains_key(user_id) {
+            return Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}/user/{}", group_id, user_id), format!("user {} does not exist", user_id)));
         }
-        match self.group_users.get_mut(group_id) {
+        match state.group_users.get_mut(group_id) {
             Some(group_users) => {
                 let _ = group_users.insert(user_id.clone());
                 Ok(())
             },
-            None => Err(anyhow::anyhow!("group {} does not exist", group_id)),
+            None => Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}", group_id), format!("group {} does not exist", group_id))),
+        }
+    }
+
+    async fn remove_user_from_group(&self, user_id: &UserID, group_id: &GroupID) -> Result<()> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        match state.group_users.get_mut(group_id) {
+            Some(group_users) => {
+                let _ = group_users.remove(user_id);
+                Ok(())
+            },
+            None => Err(galaxy_error(StatusCode::NOT_FOUND, format!("/api/groups/{}", group_id), format!("group {} does not exist", group_id))),
         }
     }
 }
@@ -140,24 +283,69 @@ impl GroupUserRepository for MockGalaxy {
 #[async_trait]
 impl RoleRepository for MockGalaxy {
     async fn get_roles(&self) -> Result<Vec<Role>> {
-        Ok(self.roles.values().cloned().collect())
+        let state = self.state();
+        Ok(state.roles.values().cloned().collect())
     }
 
-    async fn create_role(&mut self, name: &str, description: &str) -> Result<Role> {
-        let id =  self.id_generator.next();
+    async fn create_role(&self, name: &str, description: &str) -> Result<Role> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        let id = state.id_generator.next();
         let role = Role::new(&id, name, description)?;
-        self.roles.insert(id.parse()?, role.clone());
+        state.roles.insert(id.parse()?, role.clone());
         Ok(role)
     }
 }
 
 #[async_trait]
 impl UserRepository for MockGalaxy {
-    async fn get_users(&self) -> Result<Vec<User>> {
-        Ok(self.users.values().cloned().collect())
+    async fn find_user(&self, email: &Email) -> Result<Option<User>> {
+        self.user_lookups.fetch_add(1, Ordering::SeqCst);
+        let state = self.state();
+        Ok(state.users.values().find(|user| user.email == *email).cloned())
+    }
+
+    async fn find_username(&self, username: &UserName) -> Result<Option<User>> {
+        let state = self.state();
+        Ok(state.users.values().find(|user| user.username.as_ref() == Some(username)).cloned())
+    }
+
+    async fn create_user(&self, payload: &UserCreatePayload) -> Result<User> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if state.users.values().any(|user| user.email == payload.email) {
+            return Err(galaxy_error(StatusCode::CONFLICT, "/api/users".to_string(), format!("user {} already exists", payload.email)));
+        }
+        if state.users.values().any(|user| user.username.as_ref() == Some(&payload.username)) {
+            return Err(galaxy_error(StatusCode::CONFLICT, "/api/users".to_string(), format!("public name {} is taken", payload.username)));
+        }
+        let id = state.id_generator.next();
+        let mut user = User::new(&id, &payload.email.to_string())?;
+        user.username = Some(payload.username.clone());
+        state.users.insert(user.id.clone(), user.clone());
+        Ok(user)
+    }
+
+    async fn send_password_reset(&self, email: &Email) -> Result<()> {
+        if self.failing_password_resets.load(Ordering::SeqCst) {
+            return Err(galaxy_error(StatusCode::INTERNAL_SERVER_ERROR, "/user/reset_password".to_string(), "cannot send email".to_string()));
+        }
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if !state.users.values().any(|user| user.email == *email) {
+            return Err(galaxy_error(StatusCode::NOT_FOUND, "/user/reset_password".to_string(), format!("user {} does not exist", email)));
+        }
+        state.password_resets.push(email.clone());
+        Ok(())
     }
 }
 
+/// The error Galaxy would answer with, `err_msg` being its message.
+pub(crate) fn galaxy_error(status: StatusCode, endpoint: String, err_msg: String) -> anyhow::Error {
+    let body = serde_json::json!({ "err_msg": err_msg, "err_code": 0 }).to_string();
+    GalaxyError::from(ApiError { endpoint, status, body, err_msg: Some(err_msg), err_code: Some(0) }).into()
+}
+
 /*
  * IDGenerator
  */
@@ -189,7 +377,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_groups() {
-        let mut galaxy = MockGalaxy::new().unwrap();
+        let galaxy = MockGalaxy::new().unwrap();
         let group = galaxy.create_group("group2").await.unwrap();
         let groups = galaxy.get_groups().await.unwrap();
         assert!(groups.contains(&group));
@@ -197,7 +385,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_update_group() {
-        let mut galaxy = MockGalaxy::new().unwrap();
+        let galaxy = MockGalaxy::new().unwrap();
         let group = galaxy.create_group("group2").await.unwrap();
         let user_ids = vec!["user1".parse().unwrap()];
         let role_ids = vec!["role1".parse().unwrap()];
@@ -214,9 +402,19 @@ mod tests {
         assert_eq!(after_users.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_delete_group() {
+        let galaxy = MockGalaxy::new().unwrap();
+        let group = galaxy.create_group("group2").await.unwrap();
+        galaxy.delete_group(&group.id).await.unwrap();
+        assert!(!galaxy.get_groups().await.unwrap().contains(&group));
+        assert!(galaxy.get_group_users(&group.id).await.unwrap().is_empty());
+        assert!(galaxy.delete_group(&group.id).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_roles() {
-        let mut galaxy = MockGalaxy::new().unwrap();
+        let galaxy = MockGalaxy::new().unwrap();
         let roles = galaxy.get_roles().await.unwrap();
         assert_eq!(roles.len(), 1);
         let role = galaxy.create_role("role2", "role2 description").await.unwrap();
@@ -225,30 +423,51 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_users() {
+    async fn test_find_users() {
+        let mut galaxy = MockGalaxy::new().unwrap();
+        galaxy.add_user("user2", "user2@email.com").unwrap();
+        let email: Email = "user2@email.com".parse().unwrap();
+        assert_eq!(galaxy.find_user(&email).await.unwrap().unwrap().email, email);
+        assert!(galaxy.find_user(&"user3@email.com".parse().unwrap()).await.unwrap().is_none());
+        assert_eq!(galaxy.user_lookups().load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_user() {
         let galaxy = MockGalaxy::new().unwrap();
-        let users = galaxy.get_users().await.unwrap();
-        let email = "user1@email.com".parse().unwrap();
-        assert!(users.iter().map(|user| user.email.clone()).collect::<HashSet<Email>>().contains(&email));
+        let payload = UserCreatePayload {
+            username: "user2".parse().unwrap(),
+            email: "user2@email.com".parse().unwrap(),
+            password: "secret".to_string(),
+        };
+        let user = galaxy.create_user(&payload).await.unwrap();
+        assert_eq!(galaxy.find_user(&user.email).await.unwrap(), Some(user.clone()));
+        assert!(galaxy.create_user(&payload).await.is_err());
+        galaxy.send_password_reset(&user.email).await.unwrap();
+        assert_eq!(galaxy.password_resets(), vec![user.email]);
     }
 
     #[tokio::test]
     async fn test_group_roles() {
-        let mut galaxy = MockGalaxy::new().unwrap();
+        let galaxy = MockGalaxy::new().unwrap();
         let before = galaxy.get_group_roles(&"group1".parse().unwrap()).await.unwrap();
         assert_eq!(before.len(), 0);
         galaxy.add_role_to_group(&"role1".parse().unwrap(), &"group1".parse().unwrap()).await.unwrap();
         let after = galaxy.get_group_roles(&"group1".parse().unwrap()).await.unwrap();
         assert_eq!(after.len(), 1);
+        galaxy.remove_role_from_group(&"role1".parse().unwrap(), &"group1".parse().unwrap()).await.unwrap();
+        assert!(galaxy.get_group_roles(&"group1".parse().unwrap()).await.unwrap().is_empty());
     }
 
     #[tokio::test]
     async fn test_group_users() {
-        let mut galaxy = MockGalaxy::new().unwrap();
+        let galaxy = MockGalaxy::new().unwrap();
         let before = galaxy.get_group_users(&"group1".parse().unwrap()).await.unwrap();
         assert_eq!(before.len(), 0);
         galaxy.add_user_to_group(&"user1".parse().unwrap(), &"group1".parse().unwrap()).await.unwrap();
         let after = galaxy.get_group_users(&"group1".parse().unwrap()).await.unwrap();
         assert_eq!(after.len(), 1);
+        galaxy.remove_user_from_group(&"user1".parse().unwrap(), &"group1".parse().unwrap()).await.unwrap();
+        assert!(galaxy.get_group_users(&"group1".parse().unwrap()).await.unwrap().is_empty());
     }
 }
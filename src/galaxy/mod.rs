@@ -1,141 +1,253 @@
 mod config;
 pub mod types;
+mod capabilities;
 mod client;
-mod mock;
+mod error;
+mod rate_limit;
+#[cfg(test)]
+pub(crate) mod mock;
 
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
 use crate::galaxy::types::*;
 
-pub fn init_galaxy() -> Result<crate::galaxy::Galaxy> {
+pub use capabilities::Capabilities;
+pub use client::http_client;
+pub use config::{ApiKeySource, ClientSettings, GalaxySettings, parse_galaxy_url};
+pub use error::GalaxyError;
+pub use rate_limit::RateLimitMetrics;
+
+/// Connects to the Galaxy given on the command line, in the environment or in the
+/// `[galaxy]` section of the configuration file.
+pub async fn init_galaxy(galaxy_url: Option<String>, api_key: Option<String>, settings: &GalaxySettings, client: ClientSettings) -> Result<crate::galaxy::Galaxy> {
     let env_var_provider = crate::galaxy::config::RealEnvVarProvider::new();
-    let galaxy_config = crate::galaxy::config::Config::new(&env_var_provider)?;
-    Galaxy::new(galaxy_config)
+    let mut galaxy_config = crate::galaxy::config::Config::with_overrides(&env_var_provider, galaxy_url, api_key, settings)?;
+    galaxy_config.client = client;
+    Galaxy::connect(galaxy_config).await
+}
+
+/// Connects to the named instance `instance` of the configuration file.
+pub async fn init_instance(instance: &str, settings: &GalaxySettings, client: ClientSettings) -> Result<crate::galaxy::Galaxy> {
+    let mut galaxy_config = crate::galaxy::config::Config::for_instance(instance, settings)?;
+    galaxy_config.client = client;
+    Galaxy::connect(galaxy_config).await
 }
 
 #[async_trait]
-pub trait GalaxyAPI: GroupRepository + RoleRepository + UserRepository {
+pub trait GalaxyAPI: Send + Sync + GroupRepository + GroupRoleRepository + GroupUserRepository + RoleRepository + UserRepository {
+    /// The time spent waiting for the rate limit so far.
+    fn rate_limit_metrics(&self) -> RateLimitMetrics {
+        RateLimitMetrics::default()
+    }
 }
 
 #[async_trait]
 pub trait GroupRepository {
     async fn get_groups(&self) -> Result<Vec<Group>>;
-    async fn create_group(&mut self, name: &str) -> Result<Group>;
-    async fn update_group(&mut self, group_id: &GroupID, payload: &GroupUpdatePayload) -> Result<Group>;
+    async fn create_group(&self, name: &str) -> Result<Group>;
+    async fn update_group(&self, group_id: &GroupID, payload: &GroupUpdatePayload) -> Result<Group>;
+    async fn delete_group(&self, group_id: &GroupID) -> Result<()>;
 }
 
 
 #[async_trait]
 pub trait GroupRoleRepository {
     async fn get_group_roles(&self, group_id: &GroupID) -> Result<Vec<Role>>;
-    async fn add_role_to_group(&mut self, role_id: &RoleID, group_id: &GroupID) -> Result<()>;
+    async fn add_role_to_group(&self, role_id: &RoleID, group_id: &GroupID) -> Result<()>;
+    async fn remove_role_from_group(&self, role_id: &RoleID, group_id: &GroupID) -> Result<()>;
 }
 
 #[async_trait]
 pub trait GroupUserRepository {
     async fn get_group_users(&self, group_id: &GroupID) -> Result<Vec<User>>;
-    async fn add_user_to_group(&mut self, user_id: &UserID, group_id: &GroupID) -> Result<()>;
+    async fn add_user_to_group(&self, user_id: &UserID, group_id: &GroupID) -> Result<()>;
+    async fn remove_user_from_group(&self, user_id: &UserID, group_id: &GroupID) -> Result<()>;
 }
 
 #[async_trait]
 pub trait RoleRepository {
     async fn get_roles(&self) -> Result<Vec<Role>>;
-    async fn create_role(&mut self, name: &str, description: &str) -> Result<Role>;
+    async fn create_role(&self, name: &str, description: &str) -> Result<Role>;
 }
 
 #[async_trait]
 pub trait UserRepository {
-    async fn get_users(&self) -> Result<Vec<User>>;
+    /// Finds the user with this exact email, without listing every user.
+    async fn find_user(&self, email: &Email) -> Result<Option<User>>;
+    /// Finds the user with this exact public name.
+    async fn find_username(&self, username: &UserName) -> Result<Option<User>>;
+    async fn create_user(&self, payload: &UserCreatePayload) -> Result<User>;
+    async fn send_password_reset(&self, email: &Email) -> Result<()>;
 }
 
 
 pub struct Galaxy {
     client: client::Client,
+    capabilities: Capabilities,
 }
 
 impl Galaxy {
+    /// Creates a client without asking the server its release, assuming the default `Capabilities`.
     pub fn new(config: config::Config) -> Result<Galaxy> {
         Ok(Galaxy {
-            client: client::Client::new(config),
+            client: client::Client::new(config)?,
+            capabilities: Capabilities::default(),
         })
     }
+
+    /// Creates a client for a Galaxy and adapts it to the release of the server.
+    ///
+    /// Fails if the server is too old to manage the roles of groups.
+    pub async fn connect(config: config::Config) -> Result<Galaxy> {
+        let mut galaxy = Galaxy::new(config)?;
+        galaxy.capabilities = galaxy.detect_capabilities().await?;
+        tracing::debug!(version = %galaxy.capabilities.version, remote_user = galaxy.capabilities.remote_user, "connected to Galaxy");
+        Ok(galaxy)
+    }
+
+    async fn detect_capabilities(&self) -> Result<Capabilities> {
+        let endpoint = "/api/version";
+        let response = check(endpoint, self.client.get(endpoint).await?, is_ok).await?;
+        let version: capabilities::VersionResponse = decode(endpoint, response).await?;
+        let endpoint = "/api/configuration";
+        let response = check(endpoint, self.client.get(endpoint).await?, is_ok).await?;
+        let configuration: capabilities::ConfigurationResponse = decode(endpoint, response).await?;
+        Capabilities::new(version.version_major.parse()?, configuration.use_remote_user)
+    }
 }
 
 impl GalaxyAPI for Galaxy {
+    fn rate_limit_metrics(&self) -> RateLimitMetrics {
+        self.client.rate_limit_metrics()
+    }
+}
+
+/// Returns the response if `accept` its status, the `GalaxyError` matching it otherwise.
+async fn check(endpoint: &str, response: Response, accept: impl Fn(StatusCode) -> bool) -> Result<Response, GalaxyError> {
+    if accept(response.status()) {
+        Ok(response)
+    } else {
+        Err(GalaxyError::from_response(endpoint, response).await)
+    }
+}
+
+/// Decodes the JSON body of a response, keeping the body in the error if it does not match.
+async fn decode<T: DeserializeOwned>(endpoint: &str, response: Response) -> Result<T, GalaxyError> {
+    let body = response.text().await.map_err(|source| GalaxyError::Transport { endpoint: endpoint.to_string(), source })?;
+    serde_json::from_str(&body).map_err(|source| GalaxyError::Decode { endpoint: endpoint.to_string(), body, source })
+}
+
+/// Percent-encodes a query string value, keeping only unreserved characters as is.
+fn encode_query_value(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+fn is_success(status: StatusCode) -> bool {
+    status.is_success()
+}
+
+fn is_ok(status: StatusCode) -> bool {
+    status == StatusCode::OK
 }
 
 #[async_trait]
 impl UserRepository for Galaxy {
-    async fn get_users(&self) -> Result<Vec<User>> {
-        let response = self.client.get("/api/users").await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to get users: {:?}", response));
+    async fn find_user(&self, email: &Email) -> Result<Option<User>> {
+        let endpoint = format!("/api/users?f_email={}", encode_query_value(&email.to_string()));
+        let response = check(&endpoint, self.client.get(&endpoint).await?, is_ok).await?;
+        let users: Vec<User> = decode(&endpoint, response).await?;
+        // Galaxy returns every email containing the filter.
+        Ok(users.into_iter().find(|user| user.email == *email))
+    }
+
+    async fn find_username(&self, username: &UserName) -> Result<Option<User>> {
+        let endpoint = format!("/api/users?f_name={}", encode_query_value(&username.to_string()));
+        let response = check(&endpoint, self.client.get(&endpoint).await?, is_ok).await?;
+        let users: Vec<User> = decode(&endpoint, response).await?;
+        // Galaxy returns every public name containing the filter.
+        Ok(users.into_iter().find(|user| user.username.as_ref() == Some(username)))
+    }
+
+    async fn create_user(&self, payload: &UserCreatePayload) -> Result<User> {
+        let endpoint = "/api/users";
+        let response = if self.capabilities.remote_user {
+            let payload = HashMap::from([("remote_user_email", &payload.email)]);
+            self.client.post(endpoint, payload).await?
+        } else {
+            self.client.post(endpoint, payload).await?
+        };
+        let response = check(endpoint, response, is_success).await?;
+        Ok(decode(endpoint, response).await?)
+    }
+
+    async fn send_password_reset(&self, email: &Email) -> Result<()> {
+        // Remote users log in through the proxy, Galaxy has no password for them.
+        if self.capabilities.remote_user {
+            return Ok(());
         }
-        let users: Vec<User> = response.json().await?;
-        Ok(users)
+        let endpoint = "/user/reset_password";
+        let payload = HashMap::from([("email", email)]);
+        check(endpoint, self.client.post(endpoint, payload).await?, is_ok).await?;
+        Ok(())
     }
 }
 
 #[async_trait]
 impl RoleRepository for Galaxy {
     async fn get_roles(&self) -> Result<Vec<Role>> {
-        let response = self.client.get("/api/roles").await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to get roles: {:?}", response));
-        }
-        let roles: Vec<Role> = response.json().await?;
-        Ok(roles)
+        let endpoint = "/api/roles";
+        let response = check(endpoint, self.client.get(endpoint).await?, is_ok).await?;
+        Ok(decode(endpoint, response).await?)
     }
 
-    async fn create_role(&mut self, name: &str, description: &str) -> Result<Role> {
+    async fn create_role(&self, name: &str, description: &str) -> Result<Role> {
+        let endpoint = "/api/roles";
         let role = RoleDefinitionModel {
             name: name.parse()?,
             description: description.to_string(),
             user_ids: None,
             group_ids: None,
         };
-        let response = self.client.post("/api/roles", role).await?;
-        if response.status() != 201 {
-            return Err(anyhow!("Failed to create role: {:?}", response));
-        }
-        let new_role: Role = response.json().await?;
-        Ok(new_role)
+        let response = check(endpoint, self.client.post(endpoint, role).await?, is_success).await?;
+        Ok(decode(endpoint, response).await?)
     }
 }
 
 #[async_trait]
 impl GroupRepository for Galaxy {
     async fn get_groups(&self) -> Result<Vec<Group>> {
-        let response = self.client.get("/api/groups").await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to get groups: {:?}", response));
-        }
-        let groups: Vec<Group> = response.json().await?;
-        Ok(groups)
+        let endpoint = "/api/groups";
+        let response = check(endpoint, self.client.get(endpoint).await?, is_ok).await?;
+        Ok(decode(endpoint, response).await?)
     }
 
-    async fn create_group(&mut self, name: &str) -> Result<Group> {
+    async fn create_group(&self, name: &str) -> Result<Group> {
+        let endpoint = "/api/groups";
         let group = GroupCreatePayload {
             name: name.parse()?,
             user_ids: None,
             role_ids: None,
         };
-        let response = self.client.post("/api/groups", group).await?;
-        if response.status() != 201 {
-            return Err(anyhow!("Failed to create group: {:?}", response));
-        }
-        let new_group: Group = response.json().await?;
-        Ok(new_group)
+        let response = check(endpoint, self.client.post(endpoint, group).await?, is_success).await?;
+        Ok(decode(endpoint, response).await?)
     }
 
-    async fn update_group(&mut self, group_id: &GroupID, payload: &GroupUpdatePayload) -> Result<Group> {
+    async fn update_group(&self, group_id: &GroupID, payload: &GroupUpdatePayload) -> Result<Group> {
         let endpoint = format!("/api/groups/{}", group_id);
-        let response = self.client.put(endpoint.as_str(), payload).await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to update group: {:?}", response));
-        }
-        let new_group: Group = response.json().await?;
-        Ok(new_group)
+        let response = check(&endpoint, self.client.put(&endpoint, payload).await?, is_ok).await?;
+        Ok(decode(&endpoint, response).await?)
+    }
+
+    async fn delete_group(&self, group_id: &GroupID) -> Result<()> {
+        let endpoint = format!("/api/groups/{}", group_id);
+        check(&endpoint, self.client.delete(&endpoint).await?, is_success).await?;
+        Ok(())
     }
 }
 
@@ -143,20 +255,19 @@ impl GroupRepository for Galaxy {
 impl GroupUserRepository for Galaxy {
     async fn get_group_users(&self, group_id: &GroupID) -> Result<Vec<User>> {
         let endpoint = format!("/api/groups/{}/users", group_id);
-        let response = self.client.get(endpoint.as_str()).await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to get group users: {:?}", response));
-        }
-        let users: Vec<User> = response.json().await?;
-        Ok(users)
+        let response = check(&endpoint, self.client.get(&endpoint).await?, is_ok).await?;
+        Ok(decode(&endpoint, response).await?)
     }
 
-    async fn add_user_to_group(&mut self, user_id: &UserID, group_id: &GroupID) -> Result<()> {
+    async fn add_user_to_group(&self, user_id: &UserID, group_id: &GroupID) -> Result<()> {
         let endpoint = format!("/api/groups/{}/user/{}", group_id, user_id);
-        let response = self.client.put(endpoint.as_str(), ()).await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to add user to group: {:?}", response));
-        }
+        check(&endpoint, self.client.put(&endpoint, ()).await?, is_ok).await?;
+        Ok(())
+    }
+
+    async fn remove_user_from_group(&self, user_id: &UserID, group_id: &GroupID) -> Result<()> {
+        let endpoint = format!("/api/groups/{}/user/{}", group_id, user_id);
+        check(&endpoint, self.client.delete(&endpoint).await?, is_ok).await?;
         Ok(())
     }
 }
@@ -165,20 +276,19 @@ impl GroupUserRepository for Galaxy {
 impl GroupRoleRepository for Galaxy {
     async fn get_group_roles(&self, group_id: &GroupID) -> Result<Vec<Role>> {
         let endpoint = format!("/api/groups/{}/roles", group_id);
-        let response = self.client.get(endpoint.as_str()).await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to get group roles: {:?}", response));
-        }
-        let roles: Vec<Role> = response.json().await?;
-        Ok(roles)
+        let response = check(&endpoint, self.client.get(&endpoint).await?, is_ok).await?;
+        Ok(decode(&endpoint, response).await?)
     }
 
-    async fn add_role_to_group(&mut self, role_id: &RoleID, group_id: &GroupID) -> Result<()> {
+    async fn add_role_to_group(&self, role_id: &RoleID, group_id: &GroupID) -> Result<()> {
         let endpoint = format!("/api/groups/{}/roles/{}", group_id, role_id);
-        let response = self.client.put(endpoint.as_str(), ()).await?;
-        if response.status() != 200 {
-            return Err(anyhow!("Failed to add role to group: {:?}", response));
-        }
+        check(&endpoint, self.client.put(&endpoint, ()).await?, is_ok).await?;
+        Ok(())
+    }
+
+    async fn remove_role_from_group(&self, role_id: &RoleID, group_id: &GroupID) -> Result<()> {
+        let endpoint = format!("/api/groups/{}/roles/{}", group_id, role_id);
+        check(&endpoint, self.client.delete(&endpoint).await?, is_ok).await?;
         Ok(())
     }
 }
@@ -189,34 +299,145 @@ mod tests {
     use serde_json::json;
     use tokio::runtime::Runtime;
 
-    fn setup_test() -> (mockito::ServerGuard, Galaxy, Runtime) {
-        let server = mockito::Server::new();
-        let config = config::Config {
+    fn test_config(server: &mockito::ServerGuard) -> config::Config {
+        config::Config {
             galaxy_url: server.url(),
             api_key: "test-api-key".to_string(),
-        };
-        let galaxy = Galaxy::new(config).unwrap();
+            client: config::ClientSettings::default(),
+        }
+    }
+
+    fn setup_test() -> (mockito::ServerGuard, Galaxy, Runtime) {
+        let server = mockito::Server::new();
+        let galaxy = Galaxy::new(test_config(&server)).unwrap();
         let runtime = Runtime::new().unwrap();
         (server, galaxy, runtime)
     }
 
+    fn mock_version(server: &mut mockito::ServerGuard, version: &str, use_remote_user: bool) -> (mockito::Mock, mockito::Mock) {
+        let version = server.mock("GET", "/api/version")
+            .with_status(200)
+            .with_body(json!({"version_major": version, "version_minor": "1", "extra": {}}).to_string())
+            .create();
+        let configuration = server.mock("GET", "/api/configuration")
+            .match_header("x-api-key", "test-api-key")
+            .with_status(200)
+            .with_body(json!({"brand": "Galaxy", "use_remote_user": use_remote_user}).to_string())
+            .create();
+        (version, configuration)
+    }
+
     #[test]
-    fn test_get_users() {
+    fn test_connect() {
+        let mut server = mockito::Server::new();
+        let (version, configuration) = mock_version(&mut server, "23.0", true);
+        let create_group = server.mock("POST", "/api/groups")
+            .with_status(200)
+            .with_body(r#"{"id": "1", "name": "Group 1"}"#)
+            .create();
+        let create_user = server.mock("POST", "/api/users")
+            .match_body(mockito::Matcher::Json(json!({"remote_user_email": "john@example.com"})))
+            .with_status(200)
+            .with_body(r#"{"id": "1", "email": "john@example.com"}"#)
+            .create();
+        let reset = server.mock("POST", "/user/reset_password")
+            .expect(0)
+            .create();
+        let payload = UserCreatePayload {
+            username: "john".parse().unwrap(),
+            email: "john@example.com".parse().unwrap(),
+            password: "secret".to_string(),
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let galaxy = Galaxy::connect(test_config(&server)).await.unwrap();
+            assert!(galaxy.capabilities.remote_user);
+            galaxy.create_group("Group 1").await.unwrap();
+            let user = galaxy.create_user(&payload).await.unwrap();
+            galaxy.send_password_reset(&user.email).await.unwrap();
+        });
+
+        version.assert();
+        configuration.assert();
+        create_group.assert();
+        create_user.assert();
+        reset.assert();
+    }
+
+    #[test]
+    fn test_connect_too_old() {
+        let mut server = mockito::Server::new();
+        let _mocks = mock_version(&mut server, "22.05", false);
+
+        Runtime::new().unwrap().block_on(async {
+            let error = Galaxy::connect(test_config(&server)).await.err().unwrap();
+            assert!(error.to_string().starts_with("Galaxy 22.05 is too old"));
+        });
+    }
+
+    #[test]
+    fn test_find_user() {
         let (mut server, galaxy, runtime) = setup_test();
         let mock = server.mock("GET", "/api/users")
-            .match_header("x-api-key", "test-api-key")
+            .match_query(mockito::Matcher::UrlEncoded("f_email".into(), "john+training@example.com".into()))
             .with_status(200)
-            .with_body(r#"[{"id": "1", "username": "john", "email": "john@example.com"}]"#)
+            .with_body(r#"[{"id": "1", "email": "xjohn+training@example.com"}, {"id": "2", "email": "john+training@example.com"}]"#)
             .create();
 
         runtime.block_on(async {
-            let response = galaxy.get_users().await.unwrap();
-            assert_eq!(response.len(), 1);
+            let user = galaxy.find_user(&"john+training@example.com".parse().unwrap()).await.unwrap();
+            assert_eq!(user.unwrap().id, "2".parse::<UserID>().unwrap());
         });
+        assert_eq!(encode_query_value("a+b@c.d"), "a%2Bb%40c.d");
 
         mock.assert();
     }
 
+    #[test]
+    fn test_find_username() {
+        let (mut server, galaxy, runtime) = setup_test();
+        let mock = server.mock("GET", "/api/users")
+            .match_query(mockito::Matcher::UrlEncoded("f_name".into(), "john".into()))
+            .with_status(200)
+            .with_body(r#"[{"id": "1", "email": "johnny@example.com", "username": "johnny"}, {"id": "2", "email": "john@example.com", "username": "john"}]"#)
+            .create();
+
+        runtime.block_on(async {
+            let user = galaxy.find_username(&"john".parse().unwrap()).await.unwrap();
+            assert_eq!(user.unwrap().id, "2".parse::<UserID>().unwrap());
+        });
+        mock.assert();
+    }
+
+    #[test]
+    fn test_create_user() {
+        let (mut server, galaxy, runtime) = setup_test();
+        let create = server.mock("POST", "/api/users")
+            .match_header("x-api-key", "test-api-key")
+            .match_body(mockito::Matcher::PartialJson(json!({"username":"john","email":"john@example.com"})))
+            .with_status(200)
+            .with_body(r#"{"id": "1", "username": "john", "email": "john@example.com"}"#)
+            .create();
+        let reset = server.mock("POST", "/user/reset_password")
+            .match_body(mockito::Matcher::Json(json!({"email":"john@example.com"})))
+            .with_status(200)
+            .create();
+        let payload = UserCreatePayload {
+            username: "john".parse().unwrap(),
+            email: "john@example.com".parse().unwrap(),
+            password: "secret".to_string(),
+        };
+
+        runtime.block_on(async {
+            let user = galaxy.create_user(&payload).await.unwrap();
+            assert_eq!(user.id, "1".parse::<UserID>().unwrap());
+            galaxy.send_password_reset(&user.email).await.unwrap();
+        });
+
+        create.assert();
+        reset.assert();
+    }
+
     #[test]
     fn test_get_roles() {
         let (mut server, galaxy, runtime) = setup_test();
@@ -236,7 +457,7 @@ mod tests {
 
     #[test]
     fn test_create_role() {
-        let (mut server, mut galaxy, runtime) = setup_test();
+        let (mut server, galaxy, runtime) = setup_test();
         let mock = server.mock("POST", "/api/roles")
             .match_header("x-api-key", "test-api-key")
             .match_body(mockito::Matcher::PartialJson(json!({"name":"John Doe","description":"john@example.com"})))
@@ -271,7 +492,7 @@ mod tests {
 
     #[test]
     fn test_create_group() {
-        let (mut server, mut galaxy, runtime) = setup_test();
+        let (mut server, galaxy, runtime) = setup_test();
         let mock = server.mock("POST", "/api/groups")
             .match_header("x-api-key", "test-api-key")
             .match_body(mockito::Matcher::PartialJson(json!({"name":"Group 1"})))
@@ -289,7 +510,7 @@ mod tests {
 
     #[test]
     fn test_update_group() {
-        let (mut server, mut galaxy, runtime) = setup_test();
+        let (mut server, galaxy, runtime) = setup_test();
         let mock = server.mock("PUT", "/api/groups/1")
             .match_header("x-api-key", "test-api-key")
             .match_body(mockito::Matcher::PartialJson(json!({"name":"Group 1"})))
@@ -309,6 +530,21 @@ mod tests {
         mock.assert();
     }
 
+    #[test]
+    fn test_delete_group() {
+        let (mut server, galaxy, runtime) = setup_test();
+        let mock = server.mock("DELETE", "/api/groups/1")
+            .match_header("x-api-key", "test-api-key")
+            .with_status(204)
+            .create();
+
+        runtime.block_on(async {
+            galaxy.delete_group(&"1".parse::<GroupID>().unwrap()).await.unwrap();
+        });
+
+        mock.assert();
+    }
+
     #[test]
     fn test_get_group_users() {
         let (mut server, galaxy, runtime) = setup_test();
@@ -328,7 +564,7 @@ mod tests {
 
     #[test]
     fn test_add_user_to_group() {
-        let (mut server, mut galaxy, runtime) = setup_test();
+        let (mut server, galaxy, runtime) = setup_test();
         let mock = server.mock("PUT", "/api/groups/1/user/2")
             .match_header("x-api-key", "test-api-key")
             .with_status(200)
@@ -360,7 +596,7 @@ mod tests {
 
     #[test]
     fn test_add_role_to_group() {
-        let (mut server, mut galaxy, runtime) = setup_test();
+        let (mut server, galaxy, runtime) = setup_test();
         let mock = server.mock("PUT", "/api/groups/1/roles/2")
             .match_header("x-api-key", "test-api-key")
             .with_status(200)
@@ -372,4 +608,85 @@ mod tests {
 
         mock.assert();
     }
+
+    #[test]
+    fn test_remove_user_from_group() {
+        let (mut server, galaxy, runtime) = setup_test();
+        let mock = server.mock("DELETE", "/api/groups/1/user/2")
+            .match_header("x-api-key", "test-api-key")
+            .with_status(200)
+            .create();
+
+        runtime.block_on(async {
+            galaxy.remove_user_from_group(&"2".parse::<UserID>().unwrap(), &"1".parse::<GroupID>().unwrap()).await.unwrap();
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_remove_role_from_group() {
+        let (mut server, galaxy, runtime) = setup_test();
+        let mock = server.mock("DELETE", "/api/groups/1/roles/2")
+            .match_header("x-api-key", "test-api-key")
+            .with_status(200)
+            .create();
+
+        runtime.block_on(async {
+            galaxy.remove_role_from_group(&"2".parse::<RoleID>().unwrap(), &"1".parse::<GroupID>().unwrap()).await.unwrap();
+        });
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let (mut server, galaxy, runtime) = setup_test();
+        let unauthorized = server.mock("GET", "/api/users")
+            .match_query(mockito::Matcher::Any)
+            .with_status(401)
+            .with_body(r#"{"err_msg": "Provided API key is not valid.", "err_code": 401001}"#)
+            .create();
+        let forbidden = server.mock("GET", "/api/roles")
+            .with_status(403)
+            .with_body(r#"{"err_msg": "You must be an administrator to access this feature.", "err_code": 403006}"#)
+            .create();
+        let validation = server.mock("POST", "/api/groups")
+            .with_status(400)
+            .with_body(r#"{"err_msg": "A group with that name already exists.", "err_code": 400008}"#)
+            .create();
+        let not_found = server.mock("DELETE", "/api/groups/1")
+            .with_status(404)
+            .with_body("Not Found")
+            .create();
+        let invalid = server.mock("GET", "/api/groups")
+            .with_status(200)
+            .with_body("<html></html>")
+            .create();
+
+        runtime.block_on(async {
+            let error = galaxy.find_user(&"john@example.com".parse().unwrap()).await.unwrap_err();
+            let error = error.downcast_ref::<GalaxyError>().unwrap();
+            assert!(matches!(error, GalaxyError::Unauthorized(e) if e.err_code == Some(401001)));
+            assert!(error.hint().is_some());
+
+            let error = galaxy.get_roles().await.unwrap_err();
+            assert_eq!(error.to_string(), "forbidden: /api/roles returned 403 Forbidden: You must be an administrator to access this feature. (error 403006)");
+
+            let error = galaxy.create_group("group1").await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<GalaxyError>(), Some(GalaxyError::Validation(e)) if e.err_msg.as_deref() == Some("A group with that name already exists.")));
+
+            let error = galaxy.delete_group(&"1".parse::<GroupID>().unwrap()).await.unwrap_err();
+            assert_eq!(error.to_string(), "not found: /api/groups/1 returned 404 Not Found: Not Found");
+
+            let error = galaxy.get_groups().await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<GalaxyError>(), Some(GalaxyError::Decode { body, .. }) if body == "<html></html>"));
+        });
+
+        unauthorized.assert();
+        forbidden.assert();
+        validation.assert();
+        not_found.assert();
+        invalid.assert();
+    }
 }
@@ -0,0 +1,121 @@
+//! # Rate Limit Module
+//!
+//! A token bucket limiting the requests sent to Galaxy, for proxies throttling API keys.
+//!
+//! The bucket holds up to `burst` tokens and refills at `requests_per_second`. Every
+//! request takes a token; when none is left, it takes one in advance and waits until
+//! it is refilled, so waiting requests are served in the order they arrived.
+
+use std::fmt::{self, Display};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Time spent waiting for the rate limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RateLimitMetrics {
+    /// Number of requests that had to wait.
+    pub throttled_requests: u64,
+    /// Total time requests waited.
+    pub waited: Duration,
+}
+
+impl Display for RateLimitMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} request(s) waited {:.1}s for the rate limit", self.throttled_requests, self.waited.as_secs_f64())
+    }
+}
+
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+    metrics: Mutex<RateLimitMetrics>,
+}
+
+struct Bucket {
+    /// Tokens left, negative when requests are waiting for tokens taken in advance.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a full bucket of `burst` tokens, refilled at `requests_per_second`.
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimiter {
+            requests_per_second,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+            metrics: Mutex::new(RateLimitMetrics::default()),
+        }
+    }
+
+    /// Waits until a request can be sent.
+    pub async fn acquire(&self) {
+        let wait = self.take_token();
+        if wait.is_zero() {
+            return;
+        }
+        {
+            let mut metrics = self.metrics.lock().expect("rate limit metrics poisoned");
+            metrics.throttled_requests += 1;
+            metrics.waited += wait;
+        }
+        tokio::time::sleep(wait).await;
+    }
+
+    /// Takes a token, returning how long to wait for it.
+    fn take_token(&self) -> Duration {
+        let mut bucket = self.bucket.lock().expect("rate limit bucket poisoned");
+        let now = Instant::now();
+        let refilled = now.duration_since(bucket.refilled_at).as_secs_f64() * self.requests_per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst);
+        bucket.refilled_at = now;
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.requests_per_second)
+        }
+    }
+
+    /// The time spent waiting so far.
+    pub fn metrics(&self) -> RateLimitMetrics {
+        *self.metrics.lock().expect("rate limit metrics poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_token() {
+        let limiter = RateLimiter::new(10.0, 2);
+        assert_eq!(limiter.take_token(), Duration::ZERO);
+        assert_eq!(limiter.take_token(), Duration::ZERO);
+        let wait = limiter.take_token();
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100), "{:?}", wait);
+        // The next request waits behind the previous one.
+        let wait = limiter.take_token();
+        assert!(wait > Duration::from_millis(190) && wait <= Duration::from_millis(200), "{:?}", wait);
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let limiter = RateLimiter::new(50.0, 1);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(35));
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.throttled_requests, 2);
+        assert!(metrics.waited >= Duration::from_millis(35));
+        assert_eq!(RateLimitMetrics { throttled_requests: 3, waited: Duration::from_millis(1500) }.to_string(), "3 request(s) waited 1.5s for the rate limit");
+    }
+}
@@ -12,6 +12,7 @@ pub struct User {
 }
 
 impl User {
+    #[cfg(test)]
     pub fn new(id: &str, email: &str) -> Result<Self> {
         Ok(User {
             id: id.parse()?,
@@ -33,6 +34,7 @@ pub struct Role {
 }
 
 impl Role {
+    #[cfg(test)]
     pub fn new(id: &str, name: &str, description: &str) -> Result<Self> {
         Ok(Role {
             id: id.parse()?,
@@ -57,6 +59,7 @@ pub struct Group {
 }
 
 impl Group {
+    #[cfg(test)]
     pub fn new(id: &str, name: &str) -> Result<Self> {
         Ok(Group {
             id: id.parse()?,
@@ -78,6 +81,13 @@ pub struct RoleDefinitionModel {
 }
 
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UserCreatePayload {
+    pub username: UserName,
+    pub email: Email,
+    pub password: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct GroupCreatePayload {
     pub name: GroupName,
@@ -97,7 +107,7 @@ pub struct GroupUpdatePayload {
  */
 
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct UserID(String);
 
 impl Display for UserID {
@@ -113,7 +123,7 @@ impl FromStr for UserID {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct RoleID(String);
 
 impl Display for RoleID {
@@ -129,7 +139,7 @@ impl FromStr for RoleID {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct GroupID(String);
 
 impl Display for GroupID {
@@ -145,7 +155,7 @@ impl FromStr for GroupID {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct UserName(String);
 
 impl Display for UserName { 
@@ -161,7 +171,7 @@ impl FromStr for UserName {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct RoleName(String);
 
 impl Display for RoleName {
@@ -177,7 +187,7 @@ impl FromStr for RoleName {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct GroupName(String);
 
 impl Display for GroupName {
@@ -194,7 +204,7 @@ impl FromStr for GroupName {
 }
 
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct Email(String);
 
 fn is_valid_email(email: &str) -> bool {
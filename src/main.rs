@@ -1,26 +1,29 @@
+mod cli;
 mod config;
+mod daemon;
 mod galaxy;
 mod manager;
 
-use anyhow::Result;
-use crate::manager::TrainingManager;
+use clap::Parser;
+use std::process::ExitCode;
+use crate::cli::{Cli, ExitStatus, report_error};
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() != 1 {
-        println!("Usage: training-manager <config-file>");
-        return Ok(());
-    }
-    let config_path = &args[0];
-    let config: config::ConfigFile = config::read_config(config_path)?;
-
-    let galaxy = galaxy::init_galaxy()?;
-
-    let mut training_manager = TrainingManager::new(Box::new(galaxy));
+async fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() { ExitStatus::Error.into() } else { ExitStatus::Success.into() };
+        },
+    };
 
-    training_manager.apply_config(&config).await?;
-
-    Ok(())
+    cli.init_logging();
+    match cli.run().await {
+        Ok(status) => status.into(),
+        Err(e) => {
+            report_error(&e);
+            ExitStatus::Error.into()
+        },
+    }
 }
-
@@ -1,88 +1,1161 @@
-use anyhow::Result;
-use std::collections::{HashSet, HashMap};
-use crate::galaxy::{types::*, GalaxyAPI};
-use crate::config::date::Date;
-use crate::config;
+pub mod plan;
+pub mod prune;
+pub mod state;
+pub mod status;
 
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::path::Path;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use tracing::{Instrument, debug, info, info_span, warn};
+use crate::galaxy::{types::*, GalaxyAPI, GalaxyError, RateLimitMetrics};
+use chrono::{DateTime, Utc};
+use crate::config::{self, MissingUserPolicy, ReconcileMode};
+use crate::config::schedule::Schedule;
+use plan::{GroupChange, MissingUser, MissingUserAction, MissingUsersError, Plan, SetChange, UnsentPasswordReset};
+use prune::{PrunePlan, PrunedGroup};
+use state::State;
+use status::{GroupStatus, WindowStatus};
+
+/// Number of groups updated at the same time by default.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Reconciles one Galaxy with the configuration. With named instances, every instance
+/// has its own training manager, which only manages the groups reconciled on it and
+/// records what it creates in the state file of the instance.
 pub struct TrainingManager {
     galaxy: Box<dyn GalaxyAPI>,
+    /// Name of the instance, `None` for a single Galaxy.
+    instance: Option<String>,
+    /// Maximum number of groups updated at the same time.
+    concurrency: usize,
 }
 
 impl TrainingManager {
     pub fn new(galaxy: Box<dyn GalaxyAPI>) -> Self {
         TrainingManager {
             galaxy,
+            instance: None,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Makes the training manager reconcile the named instance `instance`.
+    pub fn with_instance(mut self, instance: &str) -> Self {
+        self.instance = Some(instance.to_string());
+        self
+    }
+
+    /// The name of the instance, `None` for a single Galaxy.
+    pub fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+
+    /// The configured groups reconciled on this Galaxy, sorted by name.
+    fn config_groups<'a>(&self, config: &'a config::ConfigFile) -> Vec<&'a GroupName> {
+        let mut config_groups: Vec<&GroupName> = config.groups.keys().filter(|group| config.applies_to(group, self.instance())).collect();
+        config_groups.sort();
+        config_groups
+    }
+
+    /// The time spent waiting for the Galaxy rate limit so far.
+    pub fn rate_limit_metrics(&self) -> RateLimitMetrics {
+        self.galaxy.rate_limit_metrics()
+    }
+
+    /// Sets the maximum number of groups updated at the same time, at least 1.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Creates accounts for the missing users, with a random password the users reset by email.
+    async fn create_missing_users(&self, missing_users: impl Iterator<Item=&Email>, usernames: &HashMap<Email, UserName>, state: &mut WorkingState) -> Result<()> {
+        for email in missing_users {
+            let payload = UserCreatePayload {
+                username: usernames.get(email).cloned().ok_or_else(|| anyhow!("user {} has no public name", email))?,
+                email: email.clone(),
+                password: rand::rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect(),
+            };
+            let user = self.galaxy.create_user(&payload).await?;
+            info!(user = %user.email, "created user");
+            state.user_ids.insert(user.email.clone(), user.id);
+            self.send_password_reset(&user.email, state).await;
         }
+        Ok(())
     }
 
-    async fn create_missing_roles(&mut self, missing_roles: impl Iterator<Item=&RoleName>) -> Result<()> {
-        for role in missing_roles {
-            let role = self.galaxy.create_role(&role.to_string(), "").await?;
-            println!("Created role {}", role.name);
+    /// Sends a password reset email to `email`. A failure does not stop the apply: the
+    /// user is recorded, so the next apply sends it again.
+    async fn send_password_reset(&self, email: &Email, state: &mut WorkingState) {
+        match self.galaxy.send_password_reset(email).await {
+            Ok(()) => {
+                debug!(user = %email, "sent password reset");
+                state.sent_password_resets.push(email.clone());
+            },
+            Err(error) => {
+                warn!(user = %email, "password reset not sent: {:#}", error);
+                state.unsent_password_resets.push(UnsentPasswordReset {
+                    email: email.clone(),
+                    error: format!("{:#}", error),
+                });
+            },
+        }
+    }
+
+    async fn create_missing_roles(&self, missing_roles: impl Iterator<Item=(&RoleName, &str)>, state: &mut WorkingState) -> Result<()> {
+        for (role, description) in missing_roles {
+            let role = self.galaxy.create_role(&role.to_string(), description).await?;
+            info!(role = %role.name, "created role");
+            state.role_ids.insert(role.name, role.id);
         }
         Ok(())
     }
 
-    async fn create_missing_groups(&mut self, missing_groups: impl Iterator<Item=&GroupName>) -> Result<()> {
+    async fn create_missing_groups(&self, missing_groups: impl Iterator<Item=&GroupName>, state: &mut WorkingState) -> Result<()> {
         for group in missing_groups {
             let group = self.galaxy.create_group(&group.to_string()).await?;
-            println!("Created group {}", group.name);
+            info!(group = %group.name, "created group");
+            state.created_groups.push(group.name.clone());
+            state.group_ids.insert(group.name, group.id);
         }
         Ok(())
     }
 
-    pub async fn apply_config(&mut self, config: &config::ConfigFile) -> Result<()> {
-        let (users, roles, groups) = tokio::join!(
-            self.galaxy.get_users(),
+    async fn create_missing(&self, plan: &Plan, state: &mut WorkingState) -> Result<()> {
+        self.create_missing_users(plan.create_users.iter(), &plan.usernames, state).await?;
+        let missing_roles = plan.create_roles.iter().map(|role| (role, plan.role_descriptions.get(role).map(String::as_str).unwrap_or_default()));
+        self.create_missing_roles(missing_roles, state).await?;
+        self.create_missing_groups(plan.create_groups.iter(), state).await
+    }
+
+    /// Computes the changes `apply_config` would make, without sending any mutating request.
+    pub async fn plan(&self, config: &config::ConfigFile) -> Result<Plan> {
+        self.plan_at(config, &Utc::now()).await
+    }
+
+    async fn plan_at(&self, config: &config::ConfigFile, now: &DateTime<Utc>) -> Result<Plan> {
+        let (roles, groups) = tokio::join!(
             self.galaxy.get_roles(),
             self.galaxy.get_groups()
         );
-        let (users, roles, groups) = (users?, roles?, groups?);
+        let (roles, groups) = (roles?, groups?);
 
-        let galaxy_users: HashMap<Email, User> = HashMap::from_iter(users.iter().map(|user| (user.email.clone(), user.clone())));
-        let galaxy_groups: HashSet<GroupName> = HashSet::from_iter(groups.iter().map(|group| group.name.clone()));
-        let galaxy_roles: HashSet<RoleName> = HashSet::from_iter(roles.iter().map(|role| role.name.clone()));
+        let galaxy_groups: HashMap<GroupName, GroupID> = HashMap::from_iter(groups.iter().map(|group| (group.name.clone(), group.id.clone())));
+        let galaxy_roles: HashMap<RoleName, RoleID> = HashMap::from_iter(roles.iter().map(|role| (role.name.clone(), role.id.clone())));
 
-        let training_role_name: RoleName = "training".parse()?;
-        let config_roles: HashSet<RoleName> = HashSet::from([training_role_name.clone()]);
-        let config_groups: HashSet<GroupName> = HashSet::from_iter(config.groups.iter().map(|group| group.0.clone()));
+        let config_groups = self.config_groups(config);
+        let state = config.state_for(self.instance());
+        let owned = match &state {
+            Some(path) => State::load(path)?,
+            None => State::default(),
+        };
 
-        // Missing roles and groups
-        let missing_roles = config_roles.difference(&galaxy_roles);
-        let missing_groups = config_groups.difference(&galaxy_groups);
+        let mut current_groups: HashMap<&GroupName, (Vec<User>, Vec<Role>)> = HashMap::new();
+        for group_name in config_groups.iter() {
+            if let Some(group_id) = galaxy_groups.get(*group_name) {
+                let (group_users, group_roles) = tokio::join!(
+                    self.galaxy.get_group_users(group_id),
+                    self.galaxy.get_group_roles(group_id)
+                );
+                current_groups.insert(*group_name, (group_users?, group_roles?));
+            }
+        }
+
+        // Users shared by the groups: the current members, then the configured users looked up once each.
+        let mut galaxy_users: HashMap<Email, UserID> = current_groups.values()
+            .flat_map(|(group_users, _)| group_users.iter().map(|user| (user.email.clone(), user.id.clone())))
+            .collect();
+        let unknown_users: BTreeSet<&Email> = config_groups.iter().flat_map(|group_name| config.groups[*group_name].iter()).filter(|email| !galaxy_users.contains_key(email)).collect();
+        galaxy_users.extend(self.find_users(unknown_users).await?);
 
-        self.create_missing_roles(missing_roles).await?;
-        self.create_missing_groups(missing_groups).await?;
+        // Collect every missing user up front, so nothing is changed when the policy is to fail.
+        let mut missing_users: BTreeMap<Email, Vec<GroupName>> = BTreeMap::new();
+        for group_name in config_groups.iter() {
+            for email in config.groups[*group_name].iter().filter(|email| !galaxy_users.contains_key(email)) {
+                missing_users.entry(email.clone()).or_default().push((*group_name).clone());
+            }
+        }
+        let action = match config.missing_users {
+            MissingUserPolicy::Skip => MissingUserAction::Skipped,
+            MissingUserPolicy::Fail => MissingUserAction::Failed,
+            MissingUserPolicy::Create => MissingUserAction::Created,
+        };
+        let missing_users: Vec<MissingUser> = missing_users.into_iter()
+            .map(|(email, groups)| MissingUser { email, groups, action })
+            .collect();
+        if action == MissingUserAction::Failed && !missing_users.is_empty() {
+            return Err(MissingUsersError { missing_users }.into());
+        }
 
-        // get the training role
-        let training_role_id = roles.iter().find(|role| role.name == training_role_name).unwrap().id.clone();
+        let create_roles: Vec<RoleName> = config.roles.keys().filter(|role| !galaxy_roles.contains_key(role)).cloned().collect();
+        let mut plan = Plan {
+            send_password_resets: owned.password_resets.iter().cloned().collect(),
+            create_users: missing_users.iter().filter(|missing_user| missing_user.action == MissingUserAction::Created).map(|missing_user| missing_user.email.clone()).collect(),
+            create_roles,
+            create_groups: config_groups.iter().filter(|group| !galaxy_groups.contains_key(group)).cloned().cloned().collect(),
+            ..Default::default()
+        };
+        plan.usernames = self.unique_usernames(&plan.create_users).await?;
 
-        let today = Date::now();
+        for group_name in config_groups {
+            let schedule = config.schedule.get(group_name).ok_or_else(|| anyhow!("group {} has no schedule", group_name))?;
+            let group_id = galaxy_groups.get(group_name).cloned();
 
-        for group_name in config.groups.keys() {
-            let group_users = config.groups.get(group_name).unwrap();
-            let schedule = config.schedule.get(group_name).unwrap();
-            let group = groups.iter().find(|group| group.name == *group_name).unwrap();
-            let mut user_ids = Vec::new();
-            let mut role_ids = Vec::new();
+            let after_users: BTreeSet<Email> = config.groups[group_name].iter()
+                .filter(|email| galaxy_users.contains_key(email) || plan.create_users.contains(email))
+                .cloned()
+                .collect();
 
-            for email in group_users.iter() {
-                let user = galaxy_users.get(email).unwrap();
-                user_ids.push(user.id.clone());
+            let after_roles: BTreeSet<RoleName> = schedule.iter()
+                .filter(|schedule_item| schedule_item.contains(now))
+                .flat_map(|schedule_item| schedule_item.roles().iter().cloned())
+                .collect();
+            if let Some(role) = after_roles.iter().find(|role| !config.roles.contains_key(role)) {
+                return Err(anyhow!("group {} is granted role {}, which is not declared", group_name, role));
             }
 
-            if schedule.iter().any(|schedule_item| schedule_item.contains(&today)) {
-                role_ids.push(training_role_id.clone());
+            let (before_users, before_roles): (BTreeSet<Email>, BTreeSet<RoleName>) = match current_groups.remove(group_name) {
+                Some((group_users, group_roles)) => (
+                    group_users.into_iter().map(|user| user.email).collect(),
+                    group_roles.into_iter().map(|role| role.name).collect(),
+                ),
+                None => (BTreeSet::new(), BTreeSet::new()),
+            };
+            let added_before = owned.members.get(group_name).cloned().unwrap_or_default();
+            let (after_users, after_roles, added) = match config.reconcile {
+                ReconcileMode::Replace => (after_users.clone(), after_roles, after_users),
+                // Keep the members and the undeclared roles added by hand.
+                ReconcileMode::Preserve => {
+                    let added = after_users.iter().filter(|email| added_before.contains(email) || !before_users.contains(email)).cloned().collect();
+                    (
+                        before_users.iter().filter(|email| !added_before.contains(email)).cloned().chain(after_users).collect(),
+                        before_roles.iter().filter(|role| !config.roles.contains_key(role)).cloned().chain(after_roles).collect(),
+                        added,
+                    )
+                },
+            };
+            plan.members.insert(group_name.clone(), added);
+            for email in before_users.iter().chain(after_users.iter()) {
+                if let Some(user_id) = galaxy_users.get(email) {
+                    plan.user_ids.insert(email.clone(), user_id.clone());
+                }
             }
 
-            let payload = GroupUpdatePayload {
-                name: Some(group_name.clone()),
-                user_ids: Some(user_ids),
-                role_ids: Some(role_ids),
+            let change = GroupChange {
+                name: group_name.clone(),
+                id: group_id,
+                users: SetChange::new(before_users, after_users),
+                roles: SetChange::new(before_roles, after_roles),
             };
+            if !change.is_unchanged() {
+                plan.update_groups.push(change);
+            }
+        }
+
+        plan.missing_users = missing_users;
+        plan.reconcile = config.reconcile;
+        plan.state = state;
+        plan.role_descriptions = config.roles.iter().map(|(role, description)| (role.clone(), description.clone())).collect();
+        plan.role_ids = galaxy_roles;
+        debug!(
+            create_users = plan.create_users.len(),
+            send_password_resets = plan.send_password_resets.len(),
+            create_roles = plan.create_roles.len(),
+            create_groups = plan.create_groups.len(),
+            update_groups = plan.update_groups.len(),
+            missing_users = plan.missing_users.len(),
+            "computed plan",
+        );
+        Ok(plan)
+    }
+
+    /// Derives the public names of the users to create. A name already taken in Galaxy
+    /// or by another of the users gets the first free numbered suffix, such as `jane-2`,
+    /// in the order of `emails`.
+    async fn unique_usernames(&self, emails: &[Email]) -> Result<HashMap<Email, UserName>> {
+        let mut usernames = HashMap::new();
+        let mut taken = BTreeSet::new();
+        for email in emails {
+            let base = username_for(email)?;
+            let mut username = base.clone();
+            let mut suffix = 1;
+            while taken.contains(&username) || self.galaxy.find_username(&username).await?.is_some() {
+                suffix += 1;
+                username = format!("{}-{}", base, suffix).parse()?;
+            }
+            taken.insert(username.clone());
+            usernames.insert(email.clone(), username);
+        }
+        Ok(usernames)
+    }
+
+    /// Looks up users by email, up to the concurrency limit at a time, leaving out
+    /// the emails Galaxy does not know.
+    async fn find_users(&self, emails: impl IntoIterator<Item=&Email>) -> Result<HashMap<Email, UserID>> {
+        let users: Vec<Option<User>> = stream::iter(emails)
+            .map(|email| self.galaxy.find_user(email))
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        Ok(users.into_iter().flatten().map(|user| (user.email, user.id)).collect())
+    }
+
+    /// Executes a plan computed by `plan`.
+    ///
+    /// Groups are updated concurrently, up to the concurrency limit. A failed update
+    /// does not stop the others; the failures are returned together in a `GroupUpdatesError`.
+    ///
+    /// A password reset email that cannot be sent does not stop the apply either. The
+    /// unsent ones are recorded in the state file to be sent by the next apply, and returned.
+    pub async fn apply(&self, plan: &Plan) -> Result<Vec<UnsentPasswordReset>> {
+        info!(
+            create_users = plan.create_users.len(),
+            send_password_resets = plan.send_password_resets.len(),
+            create_roles = plan.create_roles.len(),
+            create_groups = plan.create_groups.len(),
+            update_groups = plan.update_groups.len(),
+            "applying plan",
+        );
+        let mut state = WorkingState::new(plan);
+        for email in plan.send_password_resets.iter() {
+            self.send_password_reset(email, &mut state).await;
+        }
+        let created = self.create_missing(plan, &mut state).await;
+        // Record what was created even if a later creation failed, so it can be pruned.
+        if let Some(path) = &plan.state {
+            state.record_created(path)?;
+        } else if !state.unsent_password_resets.is_empty() {
+            warn!("without a state file, the password resets not sent are not retried");
+        }
+        created?;
+
+        let mut failures: Vec<GroupFailure> = stream::iter(plan.update_groups.iter())
+            .map(|change| async {
+                let result = self.update_group(change, plan.reconcile, &state)
+                    .instrument(info_span!("group", group = %change.name))
+                    .await;
+                (change.name.clone(), result)
+            })
+            .buffer_unordered(self.concurrency)
+            .filter_map(|(group, result)| async move { result.err().map(|error| GroupFailure { group, error }) })
+            .collect()
+            .await;
+        if let Some(path) = &plan.state {
+            let members = plan.members.iter().filter(|(group, _)| !failures.iter().any(|failure| failure.group == **group));
+            record_members(path, members)?;
+        }
+        if !failures.is_empty() {
+            failures.sort_by(|a, b| a.group.cmp(&b.group));
+            return Err(GroupUpdatesError { failures }.into());
+        }
+        Ok(state.unsent_password_resets)
+    }
+
+    async fn update_group(&self, change: &GroupChange, reconcile: ReconcileMode, state: &WorkingState) -> Result<()> {
+        let group_id = state.group_id(change)?;
+        match reconcile {
+            ReconcileMode::Replace => {
+                let payload = GroupUpdatePayload {
+                    name: Some(change.name.clone()),
+                    user_ids: Some(change.users.after.iter().map(|user| state.user_id(user)).collect::<Result<Vec<UserID>>>()?),
+                    role_ids: Some(change.roles.after.iter().map(|role| state.role_id(role)).collect::<Result<Vec<RoleID>>>()?),
+                };
+                self.galaxy.update_group(&group_id, &payload).await?;
+                debug!(users = ?change.users.after, roles = ?change.roles.after, "replaced members and roles");
+            },
+            // Only touch the differences, so changes made in Galaxy since the plan are kept.
+            ReconcileMode::Preserve => {
+                for user in change.users.added() {
+                    self.galaxy.add_user_to_group(&state.user_id(user)?, &group_id).await?;
+                    debug!(user = %user, "added user");
+                }
+                // A membership already removed in Galaxy is what we want.
+                for user in change.users.removed() {
+                    ignore_not_found(self.galaxy.remove_user_from_group(&state.user_id(user)?, &group_id).await)?;
+                    debug!(user = %user, "removed user");
+                }
+                for role in change.roles.added() {
+                    self.galaxy.add_role_to_group(&state.role_id(role)?, &group_id).await?;
+                    debug!(role = %role, "added role");
+                }
+                for role in change.roles.removed() {
+                    ignore_not_found(self.galaxy.remove_role_from_group(&state.role_id(role)?, &group_id).await)?;
+                    debug!(role = %role, "removed role");
+                }
+            },
+        }
+        info!(
+            added_users = change.users.added().count(),
+            removed_users = change.users.removed().count(),
+            added_roles = change.roles.added().count(),
+            removed_roles = change.roles.removed().count(),
+            "updated group",
+        );
+        Ok(())
+    }
+
+    /// Computes the plan for `config` and applies it, returning the executed plan.
+    pub async fn apply_config(&self, config: &config::ConfigFile) -> Result<Plan> {
+        async {
+            let mut plan = self.plan(config).await?;
+            plan.unsent_password_resets = self.apply(&plan).await?;
+            Ok(plan)
+        }
+        .instrument(info_span!("reconcile", instance = self.instance()))
+        .await
+    }
+
+    /// Finds the groups created by the training manager that are no longer configured.
+    ///
+    /// Nothing is pruned when the configuration has no state file.
+    pub async fn plan_prune(&self, config: &config::ConfigFile) -> Result<PrunePlan> {
+        let Some(path) = config.state_for(self.instance()) else {
+            return Ok(PrunePlan::default());
+        };
+        let owned = State::load(&path)?;
+        let groups = self.galaxy.get_groups().await?;
+        let galaxy_groups: HashMap<GroupName, GroupID> = HashMap::from_iter(groups.into_iter().map(|group| (group.name, group.id)));
+
+        let mut plan = PrunePlan {
+            state: Some(path),
+            ..Default::default()
+        };
+        // Groups moved to other instances are pruned from this one.
+        let config_groups = self.config_groups(config);
+        for group_name in owned.groups.iter().filter(|group| !config_groups.contains(group)) {
+            let Some(group_id) = galaxy_groups.get(group_name) else {
+                plan.forget_groups.push(group_name.clone());
+                continue;
+            };
+            let (users, roles) = tokio::join!(
+                self.galaxy.get_group_users(group_id),
+                self.galaxy.get_group_roles(group_id)
+            );
+            plan.delete_groups.push(PrunedGroup {
+                name: group_name.clone(),
+                id: group_id.clone(),
+                users: users?.into_iter().map(|user| user.email).collect(),
+                roles: roles?.into_iter().map(|role| role.name).collect(),
+            });
+        }
+        Ok(plan)
+    }
+
+    /// Executes a plan computed by `plan_prune`: empties and deletes its groups, and
+    /// removes them from the state file.
+    pub async fn prune(&self, plan: &PrunePlan) -> Result<()> {
+        async {
+            let mut pruned = plan.forget_groups.clone();
+            let mut result = Ok(());
+            for group in plan.delete_groups.iter() {
+                if let Err(e) = self.delete_group(group).instrument(info_span!("group", group = %group.name)).await {
+                    result = Err(e);
+                    break;
+                }
+                info!(group = %group.name, "deleted group");
+                pruned.push(group.name.clone());
+            }
+            if let Some(path) = &plan.state {
+                if !pruned.is_empty() {
+                    let mut owned = State::load(path)?;
+                    owned.groups.retain(|group| !pruned.contains(group));
+                    owned.members.retain(|group, _| !pruned.contains(group));
+                    owned.save(path)?;
+                }
+            }
+            result
+        }
+        .instrument(info_span!("prune", instance = self.instance(), delete_groups = plan.delete_groups.len()))
+        .await
+    }
+
+    async fn delete_group(&self, group: &PrunedGroup) -> Result<()> {
+        // Empty the group first, so its members lose their roles even if Galaxy keeps deleted groups around.
+        let payload = GroupUpdatePayload {
+            name: Some(group.name.clone()),
+            user_ids: Some(Vec::new()),
+            role_ids: Some(Vec::new()),
+        };
+        // A group deleted by hand since the plan is as good as pruned.
+        match self.galaxy.update_group(&group.id, &payload).await {
+            Err(e) if is_not_found(&e) => return Ok(()),
+            result => result?,
+        };
+        ignore_not_found(self.galaxy.delete_group(&group.id).await)
+    }
+
+    /// Reports, for every configured group, whether it is in training now and its current members.
+    pub async fn status(&self, config: &config::ConfigFile) -> Result<Vec<GroupStatus>> {
+        self.status_at(config, &Utc::now()).await
+    }
+
+    async fn status_at(&self, config: &config::ConfigFile, now: &DateTime<Utc>) -> Result<Vec<GroupStatus>> {
+        let groups = self.galaxy.get_groups().await?;
+        let galaxy_groups: HashMap<GroupName, GroupID> = HashMap::from_iter(groups.into_iter().map(|group| (group.name, group.id)));
+
+        let config_groups = self.config_groups(config);
+
+        let mut statuses = Vec::new();
+        for group_name in config_groups {
+            let schedule = config.schedule.get(group_name).map(Vec::as_slice).unwrap_or_default();
+            let in_training = schedule.iter().any(|schedule_item| schedule_item.contains(now));
+            let window = schedule.iter()
+                .filter_map(|schedule_item| schedule_item.next_window(now))
+                .min_by_key(|time_range| time_range.effective_start());
+            let mut users: Vec<Email> = match galaxy_groups.get(group_name) {
+                Some(group_id) => self.galaxy.get_group_users(group_id).await?.into_iter().map(|user| user.email).collect(),
+                None => Vec::new(),
+            };
+            users.sort();
+            statuses.push(GroupStatus {
+                name: group_name.clone(),
+                in_training,
+                window: window.as_ref().map(WindowStatus::from),
+                users,
+            });
+        }
+        Ok(statuses)
+    }
+}
+
+/// Returns `true` if Galaxy answered `404 Not Found`.
+fn is_not_found(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<GalaxyError>(), Some(GalaxyError::NotFound(_)))
+}
+
+/// Turns a `404 Not Found` into a success, for removals of what is already gone.
+fn ignore_not_found(result: Result<()>) -> Result<()> {
+    match result {
+        Err(e) if is_not_found(&e) => Ok(()),
+        result => result,
+    }
+}
+
+/// A group `apply` failed to update.
+#[derive(Debug)]
+pub struct GroupFailure {
+    pub group: GroupName,
+    pub error: anyhow::Error,
+}
+
+/// The error returned by `apply` when some groups could not be updated.
+#[derive(Debug)]
+pub struct GroupUpdatesError {
+    pub failures: Vec<GroupFailure>,
+}
+
+impl Display for GroupUpdatesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} group(s) could not be updated:", self.failures.len())?;
+        for failure in self.failures.iter() {
+            write!(f, "\n  {}: {:#}", failure.group, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GroupUpdatesError {}
+
+/// An instance a command failed on.
+#[derive(Debug)]
+pub struct InstanceFailure {
+    pub instance: String,
+    pub error: anyhow::Error,
+}
+
+/// The error returned when a command failed on some named instances, once it ran on all of them.
+#[derive(Debug)]
+pub struct InstanceFailuresError {
+    pub failures: Vec<InstanceFailure>,
+}
+
+impl Display for InstanceFailuresError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance(s) failed:", self.failures.len())?;
+        for failure in self.failures.iter() {
+            write!(f, "\n  {}: {:#}", failure.instance, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InstanceFailuresError {}
+
+/// The ids `apply` refers to: those the plan found in Galaxy, and those returned
+/// by Galaxy for the users, roles and groups created while applying it.
+struct WorkingState {
+    user_ids: HashMap<Email, UserID>,
+    role_ids: HashMap<RoleName, RoleID>,
+    group_ids: HashMap<GroupName, GroupID>,
+    created_groups: Vec<GroupName>,
+    sent_password_resets: Vec<Email>,
+    unsent_password_resets: Vec<UnsentPasswordReset>,
+}
+
+impl WorkingState {
+    fn new(plan: &Plan) -> Self {
+        WorkingState {
+            user_ids: plan.user_ids.clone(),
+            role_ids: plan.role_ids.clone(),
+            group_ids: plan.update_groups.iter()
+                .filter_map(|change| Some((change.name.clone(), change.id.clone()?)))
+                .collect(),
+            created_groups: Vec::new(),
+            sent_password_resets: Vec::new(),
+            unsent_password_resets: Vec::new(),
+        }
+    }
+
+    /// Adds the created groups and the unsent password resets to the state file at
+    /// `path`, and removes the password resets sent.
+    fn record_created(&self, path: &Path) -> Result<()> {
+        if self.created_groups.is_empty() && self.sent_password_resets.is_empty() && self.unsent_password_resets.is_empty() {
+            return Ok(());
+        }
+        let mut owned = State::load(path)?;
+        owned.groups.extend(self.created_groups.iter().cloned());
+        owned.password_resets.retain(|email| !self.sent_password_resets.contains(email));
+        owned.password_resets.extend(self.unsent_password_resets.iter().map(|reset| reset.email.clone()));
+        owned.save(path)
+    }
+
+    fn user_id(&self, email: &Email) -> Result<UserID> {
+        self.user_ids.get(email).cloned().ok_or_else(|| anyhow!("user {} does not exist", email))
+    }
+
+    fn role_id(&self, role: &RoleName) -> Result<RoleID> {
+        self.role_ids.get(role).cloned().ok_or_else(|| anyhow!("role {} does not exist", role))
+    }
+
+    fn group_id(&self, change: &GroupChange) -> Result<GroupID> {
+        self.group_ids.get(&change.name).cloned().ok_or_else(|| anyhow!("group {} was not created", change.name))
+    }
+}
 
-            self.galaxy.update_group(&group.id, &payload).await?;
+/// Records the members added to the groups of `members` in the state file at `path`,
+/// only writing it when the record changes.
+fn record_members<'a>(path: &Path, members: impl Iterator<Item=(&'a GroupName, &'a BTreeSet<Email>)>) -> Result<()> {
+    let mut owned = State::load(path)?;
+    let mut changed = false;
+    for (group, members) in members {
+        if owned.members.get(group).map_or(members.is_empty(), |recorded| recorded == members) {
+            continue;
         }
+        changed = true;
+        if members.is_empty() {
+            owned.members.remove(group);
+        } else {
+            owned.members.insert(group.clone(), members.clone());
+        }
+    }
+    if changed {
+        owned.save(path)?;
+    }
+    Ok(())
+}
+
+/// Derives a Galaxy public name from an email: its lowercase local part, with the
+/// characters Galaxy does not allow replaced by `-`, at least 3 characters long.
+fn username_for(email: &Email) -> Result<UserName> {
+    let email = email.to_string();
+    let local_part = email.split('@').next().unwrap_or_default().to_lowercase();
+    let username: String = local_part.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' { c } else { '-' })
+        .collect();
+    format!("{:-<3}", username).parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::galaxy::mock::MockGalaxy;
+    use crate::galaxy::{GroupRoleRepository, GroupUserRepository, UserRepository};
+
+    const CONFIG: &str = r#"
+    [groups]
+    group1 = ["user1@email.com"]
+    group2 = ["user1@email.com", "user2@email.com"]
+
+    [schedule]
+    group1 = [{ from = "2024-01-01", to = "2024-01-31" }]
+    group2 = { lead = "1d", lag = "12h", windows = [{ from = "2024-02-01", to = "2024-02-28" }] }
+    "#;
+
+    fn galaxy() -> MockGalaxy {
+        let mut galaxy = MockGalaxy::new().unwrap();
+        galaxy.add_user("user2", "user2@email.com").unwrap();
+        galaxy
+    }
+
+    #[tokio::test]
+    async fn test_plan() -> Result<()> {
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let plan = manager.plan_at(&config, &"2024-01-15T12:00:00Z".parse()?).await?;
+
+        assert_eq!(plan.create_roles, vec!["training".parse()?]);
+        assert_eq!(plan.create_groups, vec!["group2".parse()?]);
+        assert_eq!(plan.update_groups.len(), 2);
+        let group1 = &plan.update_groups[0];
+        assert_eq!(group1.id, Some("group1".parse()?));
+        assert!(group1.users.before.is_empty());
+        assert_eq!(group1.roles.after, BTreeSet::from(["training".parse()?]));
+        let group2 = &plan.update_groups[1];
+        assert_eq!(group2.id, None);
+        assert_eq!(group2.users.after.len(), 2);
+        assert!(group2.roles.after.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_looks_up_configured_users_once() -> Result<()> {
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let galaxy = galaxy();
+        let user_lookups = galaxy.user_lookups();
+        let manager = TrainingManager::new(Box::new(galaxy));
+        let now = "2024-02-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        // user1 is in both groups.
+        assert_eq!(user_lookups.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(plan.user_ids.len(), 2);
+
+        // The members of the groups are known without looking them up.
+        manager.apply(&plan).await?;
+        assert!(manager.plan_at(&config, &now).await?.is_empty());
+        assert_eq!(user_lookups.load(std::sync::atomic::Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_matches_plan() -> Result<()> {
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        manager.apply(&plan).await?;
+
+        let group1 = "group1".parse()?;
+        let roles = manager.galaxy.get_group_roles(&group1).await?;
+        let users = manager.galaxy.get_group_users(&group1).await?;
+        assert_eq!(roles.into_iter().map(|role| role.name).collect::<Vec<_>>(), vec!["training".parse()?]);
+        assert_eq!(users.into_iter().map(|user| user.email).collect::<Vec<_>>(), vec!["user1@email.com".parse()?]);
+
+        let plan = manager.plan_at(&config, &now).await?;
+        assert!(plan.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_status() -> Result<()> {
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        manager.apply(&plan).await?;
+
+        let statuses = manager.status_at(&config, &now).await?;
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses[0].in_training);
+        assert_eq!(statuses[0].users, vec!["user1@email.com".parse()?]);
+        let window = statuses[0].window.as_ref().unwrap();
+        assert_eq!(window.nominal_start, window.effective_start);
+        assert!(!statuses[1].in_training);
+        let window = statuses[1].window.as_ref().unwrap();
+        assert_eq!(window.nominal_start.to_rfc3339(), "2024-02-01T00:00:00+00:00");
+        assert_eq!(window.effective_start.to_rfc3339(), "2024-01-31T00:00:00+00:00");
+        assert_eq!(window.effective_end.to_rfc3339(), "2024-02-29T12:00:00+00:00");
+        assert_eq!(statuses[1].users.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_grace_period() -> Result<()> {
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let plan = manager.plan_at(&config, &"2024-01-31T12:00:00Z".parse()?).await?;
+        let group2 = plan.update_groups.iter().find(|change| change.name == "group2".parse().unwrap()).unwrap();
+        assert_eq!(group2.roles.after, BTreeSet::from(["training".parse()?]));
+        let plan = manager.plan_at(&config, &"2024-02-29T12:00:00Z".parse()?).await?;
+        let group2 = plan.update_groups.iter().find(|change| change.name == "group2".parse().unwrap()).unwrap();
+        assert!(group2.roles.after.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_multiple_roles() -> Result<()> {
+        let config: config::ConfigFile = r#"
+        [roles]
+        training = "Training"
+        gpu-training = "Training on GPU nodes"
+
+        [groups]
+        group1 = ["user1@email.com"]
+
+        [schedule]
+        group1 = { roles = ["training", "gpu-training"], windows = [
+            { from = "2024-01-01", to = "2024-01-31" },
+            { from = "2024-01-15", to = "2024-01-16", roles = ["role1"] },
+        ] }
+        "#.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let error = manager.plan_at(&config, &"2024-01-15T12:00:00Z".parse()?).await.unwrap_err();
+        assert_eq!(error.to_string(), "group group1 is granted role role1, which is not declared");
+
+        let config: config::ConfigFile = r#"
+        [roles]
+        training = "Training"
+        gpu-training = "Training on GPU nodes"
+        role1 = "role1 description"
+
+        [groups]
+        group1 = ["user1@email.com"]
+
+        [schedule]
+        group1 = { roles = ["training", "gpu-training"], windows = [
+            { from = "2024-01-01", to = "2024-01-31" },
+            { from = "2024-01-15", to = "2024-01-16", roles = ["role1"] },
+        ] }
+        "#.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        assert_eq!(plan.create_roles, vec!["gpu-training".parse()?, "training".parse()?]);
+        assert_eq!(plan.update_groups[0].roles.after, BTreeSet::from(["gpu-training".parse()?, "role1".parse()?, "training".parse()?]));
+        manager.apply(&plan).await?;
+        assert!(manager.plan_at(&config, &now).await?.is_empty());
+
+        let plan = manager.plan_at(&config, &"2024-01-20T12:00:00Z".parse()?).await?;
+        assert_eq!(plan.update_groups[0].roles.removed().collect::<Vec<_>>(), vec![&"role1".parse()?]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preserve_unmanaged_members_and_roles() -> Result<()> {
+        let path = state_path("preserve");
+        let mut config: config::ConfigFile = format!("reconcile = \"preserve\"\n{}", CONFIG).parse()?;
+        config.state = Some(path.clone());
+        let mut galaxy = galaxy();
+        galaxy.add_user("user3", "user3@email.com")?;
+        let group1: GroupID = "group1".parse()?;
+        galaxy.add_user_to_group(&"user3".parse()?, &group1).await?;
+        galaxy.add_role_to_group(&"role1".parse()?, &group1).await?;
+        let manager = TrainingManager::new(Box::new(galaxy));
+
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        let change = &plan.update_groups[0];
+        assert_eq!(change.users.added().collect::<Vec<_>>(), vec![&"user1@email.com".parse()?]);
+        assert_eq!(change.users.removed().count(), 0);
+        assert_eq!(change.roles.removed().count(), 0);
+        manager.apply(&plan).await?;
+        assert_eq!(State::load(&path)?.members[&"group1".parse()?], BTreeSet::from(["user1@email.com".parse()?]));
+        let mut roles: Vec<RoleName> = manager.galaxy.get_group_roles(&group1).await?.into_iter().map(|role| role.name).collect();
+        roles.sort();
+        assert_eq!(roles, vec!["role1".parse()?, "training".parse()?]);
+        assert_eq!(manager.galaxy.get_group_users(&group1).await?.len(), 2);
+        assert!(manager.plan_at(&config, &now).await?.is_empty());
+
+        let later = "2024-03-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &later).await?;
+        assert_eq!(plan.update_groups[0].roles.removed().collect::<Vec<_>>(), vec![&"training".parse()?]);
+        manager.apply(&plan).await?;
+        let roles = manager.galaxy.get_group_roles(&group1).await?;
+        assert_eq!(roles.into_iter().map(|role| role.name).collect::<Vec<_>>(), vec!["role1".parse()?]);
+        assert_eq!(manager.galaxy.get_group_users(&group1).await?.len(), 2);
+
+        // The member added by the training manager is removed once it is no longer configured, the one added by hand stays.
+        let mut dropped: config::ConfigFile = format!("reconcile = \"preserve\"\n{}", CONFIG.replace("group1 = [\"user1@email.com\"]", "group1 = []")).parse()?;
+        dropped.state = Some(path.clone());
+        let plan = manager.plan_at(&dropped, &later).await?;
+        assert_eq!(plan.update_groups[0].users.removed().collect::<Vec<_>>(), vec![&"user1@email.com".parse()?]);
+        manager.apply(&plan).await?;
+        let users = manager.galaxy.get_group_users(&group1).await?;
+        let owned = State::load(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(users.into_iter().map(|user| user.email).collect::<Vec<_>>(), vec!["user3@email.com".parse()?]);
+        assert!(!owned?.members.contains_key(&"group1".parse()?));
+
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let plan = manager.plan_at(&config, &later).await?;
+        assert_eq!(plan.update_groups[0].users.added().collect::<Vec<_>>(), vec![&"user1@email.com".parse()?]);
+        assert_eq!(plan.update_groups[0].users.removed().collect::<Vec<_>>(), vec![&"user3@email.com".parse()?]);
+        assert_eq!(plan.update_groups[0].roles.removed().collect::<Vec<_>>(), vec![&"role1".parse()?]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_first_run_on_empty_galaxy() -> Result<()> {
+        let mut galaxy = MockGalaxy::empty();
+        galaxy.add_user("user1", "user1@email.com")?;
+        galaxy.add_user("user2", "user2@email.com")?;
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        assert_eq!(plan.create_roles, vec!["training".parse()?]);
+        assert_eq!(plan.create_groups, vec!["group1".parse()?, "group2".parse()?]);
+        manager.apply(&plan).await?;
+
+        let groups = manager.galaxy.get_groups().await?;
+        let group1 = groups.iter().find(|group| group.name == "group1".parse().unwrap()).unwrap();
+        let roles = manager.galaxy.get_group_roles(&group1.id).await?;
+        assert_eq!(roles.into_iter().map(|role| role.name).collect::<Vec<_>>(), vec!["training".parse()?]);
+        assert!(manager.plan_at(&config, &now).await?.is_empty());
+        Ok(())
+    }
+
+    fn state_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("training-manager-{}-{}.state.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_prune() -> Result<()> {
+        let path = state_path("prune");
+        let mut config: config::ConfigFile = CONFIG.parse()?;
+        config.state = Some(path.clone());
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        let now = "2024-01-31T12:00:00Z".parse()?;
+        manager.apply(&manager.plan_at(&config, &now).await?).await?;
+        let owned = State::load(&path)?;
+        assert_eq!(owned.groups, BTreeSet::from(["group2".parse()?]));
+        assert!(manager.plan_prune(&config).await?.is_empty());
+
+        // group1 existed before the training manager, so it is never pruned.
+        config.groups.clear();
+        let plan = manager.plan_prune(&config).await?;
+        assert_eq!(plan.delete_groups.len(), 1);
+        let group2 = &plan.delete_groups[0];
+        assert_eq!(group2.name, "group2".parse()?);
+        assert_eq!(group2.users.len(), 2);
+        assert_eq!(group2.roles, BTreeSet::from(["training".parse()?]));
+        manager.prune(&plan).await?;
+
+        let groups = manager.galaxy.get_groups().await?;
+        let owned = State::load(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(groups.into_iter().map(|group| group.name).collect::<Vec<_>>(), vec!["group1".parse()?]);
+        assert!(owned?.groups.is_empty());
+        assert!(manager.plan_prune(&config).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_instances() -> Result<()> {
+        let path = state_path("instances");
+        let mut config: config::ConfigFile = r#"
+        [galaxy.main]
+        url = "https://main.example.org"
+        api_key = "main"
+
+        [galaxy.gpu]
+        url = "https://gpu.example.org"
+        api_key = "gpu"
+
+        [groups]
+        group1 = ["user1@email.com"]
+        group2 = ["user2@email.com"]
+
+        [schedule]
+        group1 = [{ from = "2024-01-01", to = "2024-01-31" }]
+        group2 = { instances = ["gpu"], windows = [{ from = "2024-01-01", to = "2024-01-31" }] }
+        "#.parse()?;
+        config.state = Some(path.clone());
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let main = TrainingManager::new(Box::new(galaxy())).with_instance("main");
+        let gpu = TrainingManager::new(Box::new(galaxy())).with_instance("gpu");
+
+        let plan = main.plan_at(&config, &now).await?;
+        assert!(plan.create_groups.is_empty());
+        assert_eq!(plan.update_groups.iter().map(|change| change.name.clone()).collect::<Vec<_>>(), vec!["group1".parse()?]);
+        main.apply(&plan).await?;
+        let plan = gpu.plan_at(&config, &now).await?;
+        assert_eq!(plan.create_groups, vec!["group2".parse()?]);
+        gpu.apply(&plan).await?;
+
+        // Each instance records what it created in its own state file.
+        let (main_path, gpu_path) = (config.state_for(Some("main")).unwrap(), config.state_for(Some("gpu")).unwrap());
+        let (main_owned, gpu_owned) = (State::load(&main_path)?, State::load(&gpu_path)?);
+        assert!(main_owned.groups.is_empty());
+        assert_eq!(gpu_owned.groups, BTreeSet::from(["group2".parse()?]));
+
+        // A group moved to another instance is pruned from the one it left.
+        config.group_instances.insert("group2".parse()?, BTreeSet::from(["main".to_string()]));
+        let plan = gpu.plan_prune(&config).await?;
+        std::fs::remove_file(&main_path)?;
+        std::fs::remove_file(&gpu_path)?;
+        assert_eq!(plan.delete_groups.iter().map(|group| group.name.clone()).collect::<Vec<_>>(), vec!["group2".parse()?]);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prune_group_deleted_in_galaxy() -> Result<()> {
+        let path = state_path("prune-deleted");
+        let mut config: config::ConfigFile = CONFIG.parse()?;
+        config.state = Some(path.clone());
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        manager.apply(&manager.plan_at(&config, &"2024-01-31T12:00:00Z".parse()?).await?).await?;
+        config.groups.clear();
+        let plan = manager.plan_prune(&config).await?;
+
+        // Deleted by hand between the plan and the prune: Galaxy answers 404.
+        manager.galaxy.delete_group(&plan.delete_groups[0].id).await?;
+        let error = manager.galaxy.delete_group(&plan.delete_groups[0].id).await.unwrap_err();
+        assert!(is_not_found(&error));
+        let result = manager.prune(&plan).await;
+        let owned = State::load(&path);
+        std::fs::remove_file(&path)?;
+        result?;
+        assert!(owned?.groups.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prune_without_state() -> Result<()> {
+        let mut config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(galaxy()));
+        manager.apply(&manager.plan_at(&config, &"2024-01-15T12:00:00Z".parse()?).await?).await?;
+        config.groups.clear();
+        assert!(manager.plan_prune(&config).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_concurrently_and_collect_failures() -> Result<()> {
+        let groups: Vec<String> = (1..=6).map(|i| format!("group{} = [\"user1@email.com\"]", i)).collect();
+        let schedule: Vec<String> = (1..=6).map(|i| format!("group{} = [{{ from = \"2024-01-01\", to = \"2024-01-31\" }}]", i)).collect();
+        let config: config::ConfigFile = format!("[groups]\n{}\n[schedule]\n{}\n", groups.join("\n"), schedule.join("\n")).parse()?;
+        let mut galaxy = galaxy();
+        let max_in_flight = galaxy.slow_updates(std::time::Duration::from_millis(20));
+        galaxy.fail_updates("group1")?;
+        let manager = TrainingManager::new(Box::new(galaxy)).with_concurrency(2);
+
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        let error = manager.apply(&plan).await.unwrap_err();
+        let error = error.downcast_ref::<GroupUpdatesError>().unwrap();
+        assert_eq!(error.failures.len(), 1);
+        assert_eq!(error.failures[0].group, "group1".parse()?);
+        assert_eq!(max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        let plan = manager.plan_at(&config, &now).await?;
+        assert_eq!(plan.update_groups.iter().map(|change| change.name.clone()).collect::<Vec<_>>(), vec!["group1".parse()?]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_unknown_user() -> Result<()> {
+        let config: config::ConfigFile = CONFIG.parse()?;
+        let manager = TrainingManager::new(Box::new(MockGalaxy::new()?));
+        let error = manager.plan_at(&config, &"2024-01-15T12:00:00Z".parse()?).await.unwrap_err();
+        let error = error.downcast_ref::<MissingUsersError>().unwrap();
+        assert_eq!(error.missing_users.len(), 1);
+        assert_eq!(error.missing_users[0].email, "user2@email.com".parse()?);
+        assert_eq!(error.missing_users[0].groups, vec!["group2".parse()?]);
+        assert_eq!(error.missing_users[0].action, MissingUserAction::Failed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_unknown_user() -> Result<()> {
+        let config: config::ConfigFile = format!("missing_users = \"skip\"\n{}", CONFIG).parse()?;
+        let manager = TrainingManager::new(Box::new(MockGalaxy::new()?));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        assert_eq!(plan.skipped_users().count(), 1);
+        assert!(plan.create_users.is_empty());
+        manager.apply(&plan).await?;
+
+        let plan = manager.plan_at(&config, &now).await?;
+        assert!(plan.is_empty());
+        assert_eq!(plan.missing_users[0].email, "user2@email.com".parse()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_unknown_user() -> Result<()> {
+        let config: config::ConfigFile = format!("missing_users = \"create\"\n{}", CONFIG).parse()?;
+        let manager = TrainingManager::new(Box::new(MockGalaxy::new()?));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        assert_eq!(plan.create_users, vec!["user2@email.com".parse()?]);
+        manager.apply(&plan).await?;
+
+        let plan = manager.plan_at(&config, &now).await?;
+        assert!(plan.is_empty());
+        assert!(plan.missing_users.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_unsent_password_resets() -> Result<()> {
+        let path = state_path("password-resets");
+        let mut config: config::ConfigFile = format!("missing_users = \"create\"\n{}", CONFIG).parse()?;
+        config.state = Some(path.clone());
+        let mut galaxy = MockGalaxy::new()?;
+        let failing = galaxy.fail_password_resets();
+        let manager = TrainingManager::new(Box::new(galaxy));
+        let now = "2024-02-15T12:00:00Z".parse()?;
+
+        // The user is created and added to its group even though the email is not sent.
+        let plan = manager.plan_at(&config, &now).await?;
+        let unsent = manager.apply(&plan).await?;
+        assert_eq!(unsent.iter().map(|reset| &reset.email).collect::<Vec<_>>(), vec![&"user2@email.com".parse()?]);
+        let plan = manager.plan_at(&config, &now).await?;
+        assert!(plan.create_users.is_empty() && plan.update_groups.is_empty());
+        assert_eq!(plan.send_password_resets, vec!["user2@email.com".parse()?]);
+        assert_eq!(plan.to_string(), "+ password reset user2@email.com\n");
+
+        // Still failing, the reset stays pending.
+        assert_eq!(manager.apply(&plan).await?.len(), 1);
+        assert_eq!(State::load(&path)?.password_resets, BTreeSet::from(["user2@email.com".parse()?]));
+
+        failing.store(false, std::sync::atomic::Ordering::SeqCst);
+        let unsent = manager.apply(&manager.plan_at(&config, &now).await?).await?;
+        let owned = State::load(&path);
+        let plan = manager.plan_at(&config, &now).await?;
+        std::fs::remove_file(&path)?;
+        assert!(unsent.is_empty());
+        assert!(owned?.password_resets.is_empty());
+        assert!(plan.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_users_with_colliding_usernames() -> Result<()> {
+        let config: config::ConfigFile = r#"
+        missing_users = "create"
+
+        [groups]
+        group1 = ["jane@example.com", "jane@example.org", "Jane@example.net"]
+
+        [schedule]
+        group1 = [{ from = "2024-01-01", to = "2024-01-31" }]
+        "#.parse()?;
+        let galaxy = MockGalaxy::new()?;
+        galaxy.create_user(&UserCreatePayload {
+            username: "jane".parse()?,
+            email: "jane.doe@example.com".parse()?,
+            password: String::new(),
+        }).await?;
+        let manager = TrainingManager::new(Box::new(galaxy));
+        let now = "2024-01-15T12:00:00Z".parse()?;
+        let plan = manager.plan_at(&config, &now).await?;
+        let usernames: Vec<String> = plan.create_users.iter().map(|email| plan.usernames[email].to_string()).collect();
+        assert_eq!(usernames, vec!["jane-2", "jane-3", "jane-4"]);
+        manager.apply(&plan).await?;
+        assert!(manager.plan_at(&config, &now).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_username_for() -> Result<()> {
+        assert_eq!(username_for(&"Jane.Doe+training@example.com".parse()?)?, "jane.doe-training".parse()?);
+        assert_eq!(username_for(&"jd@example.com".parse()?)?, "jd-".parse()?);
         Ok(())
     }
 }
@@ -0,0 +1,281 @@
+//! # Plan Module
+//!
+//! A `Plan` lists every mutating request `TrainingManager::apply_config` sends to
+//! Galaxy: the users, roles and groups to create and the `update_group` calls to
+//! issue, with the users and roles of each group before and after the update.
+//!
+//! Configured emails without a Galaxy account are reported in `missing_users`,
+//! along with what the configured `MissingUserPolicy` does about them. The password
+//! reset emails `apply` could not send to the users it created are reported in
+//! `unsent_password_resets` of the applied plan, and sent again by the next one.
+//!
+//! With `ReconcileMode::Preserve`, the users and undeclared roles found in a group
+//! are kept in its after sets, and `apply` sends the differences one at a time.
+//!
+//! Plans are computed without side effects and `TrainingManager::apply` executes
+//! them as is, so what `plan` prints is exactly what `apply` does.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use crate::config::ReconcileMode;
+use crate::galaxy::types::*;
+
+/// The set of changes needed to bring Galaxy in line with a configuration.
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    /// Users that do not exist yet in Galaxy, created with the `create` policy.
+    pub create_users: Vec<Email>,
+    /// Users created by an earlier apply whose password reset email is still to be sent.
+    pub send_password_resets: Vec<Email>,
+    /// Roles that do not exist yet in Galaxy.
+    pub create_roles: Vec<RoleName>,
+    /// Groups that do not exist yet in Galaxy.
+    pub create_groups: Vec<GroupName>,
+    /// Groups whose users or roles differ from the configuration.
+    pub update_groups: Vec<GroupChange>,
+    /// Configured emails that do not exist in Galaxy.
+    pub missing_users: Vec<MissingUser>,
+    /// Password reset emails `apply` could not send, empty until the plan is applied.
+    pub unsent_password_resets: Vec<UnsentPasswordReset>,
+    /// Public names of the users to create, unique in Galaxy.
+    #[serde(skip)]
+    pub(crate) usernames: HashMap<Email, UserName>,
+    /// Ids of the users that already exist, used to build update payloads.
+    #[serde(skip)]
+    pub(crate) user_ids: HashMap<Email, UserID>,
+    /// Ids of the roles that already exist, used to build update payloads.
+    #[serde(skip)]
+    pub(crate) role_ids: HashMap<RoleName, RoleID>,
+    /// Descriptions of the configured roles, used to create them.
+    #[serde(skip)]
+    pub(crate) role_descriptions: HashMap<RoleName, String>,
+    /// Whether groups are updated as a whole or one member and role at a time.
+    #[serde(skip)]
+    pub(crate) reconcile: ReconcileMode,
+    /// State file the created groups are recorded in.
+    #[serde(skip)]
+    pub(crate) state: Option<PathBuf>,
+    /// Configured members added by the training manager to every group, recorded in
+    /// the state file once the group is updated.
+    #[serde(skip)]
+    pub(crate) members: BTreeMap<GroupName, BTreeSet<Email>>,
+}
+
+/// A configured email without a Galaxy account.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MissingUser {
+    pub email: Email,
+    /// Configured groups listing the email.
+    pub groups: Vec<GroupName>,
+    pub action: MissingUserAction,
+}
+
+/// A password reset email `apply` could not send. The user is recorded in the state
+/// file, and the next apply sends it again.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnsentPasswordReset {
+    pub email: Email,
+    pub error: String,
+}
+
+/// What a plan does about a missing user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingUserAction {
+    /// Left out of its groups until it registers.
+    Skipped,
+    /// Prevented the plan from being computed.
+    Failed,
+    /// Created, with a password reset email sent to the user.
+    Created,
+}
+
+/// The error returned instead of a plan when users are missing with the `fail` policy.
+#[derive(Debug)]
+pub struct MissingUsersError {
+    pub missing_users: Vec<MissingUser>,
+}
+
+impl Display for MissingUsersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} user(s) do not exist in Galaxy:", self.missing_users.len())?;
+        for missing_user in self.missing_users.iter() {
+            write!(f, " {} ({})", missing_user.email, join(&missing_user.groups))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MissingUsersError {}
+
+fn join(groups: &[GroupName]) -> String {
+    groups.iter().map(GroupName::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// A single `update_group` call.
+#[derive(Debug, Serialize)]
+pub struct GroupChange {
+    pub name: GroupName,
+    /// `None` when the group is created by the same plan.
+    pub id: Option<GroupID>,
+    pub users: SetChange<Email>,
+    pub roles: SetChange<RoleName>,
+}
+
+/// The content of a set before and after a change.
+#[derive(Debug, Serialize)]
+pub struct SetChange<T: Ord> {
+    pub before: BTreeSet<T>,
+    pub after: BTreeSet<T>,
+}
+
+impl<T: Ord> SetChange<T> {
+    pub fn new(before: BTreeSet<T>, after: BTreeSet<T>) -> Self {
+        SetChange {
+            before,
+            after,
+        }
+    }
+
+    /// Items present after the change but not before.
+    pub fn added(&self) -> impl Iterator<Item=&T> {
+        self.after.difference(&self.before)
+    }
+
+    /// Items present before the change but not after.
+    pub fn removed(&self) -> impl Iterator<Item=&T> {
+        self.before.difference(&self.after)
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        self.before == self.after
+    }
+}
+
+impl GroupChange {
+    pub fn is_unchanged(&self) -> bool {
+        self.users.is_unchanged() && self.roles.is_unchanged()
+    }
+}
+
+impl Plan {
+    /// Returns `true` if applying the plan would not send any mutating request.
+    pub fn is_empty(&self) -> bool {
+        self.create_users.is_empty() && self.send_password_resets.is_empty() && self.create_roles.is_empty() && self.create_groups.is_empty() && self.update_groups.is_empty()
+    }
+
+    /// Missing users left out of their groups.
+    pub fn skipped_users(&self) -> impl Iterator<Item=&MissingUser> {
+        self.missing_users.iter().filter(|missing_user| missing_user.action == MissingUserAction::Skipped)
+    }
+}
+
+impl Display for Plan {
+    /// Formats the plan as a human readable diff, followed by the skipped users and
+    /// the password reset emails that could not be sent.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            writeln!(f, "No changes.")?;
+        }
+        for user in self.create_users.iter() {
+            writeln!(f, "+ user {}", user)?;
+        }
+        for user in self.send_password_resets.iter() {
+            writeln!(f, "+ password reset {}", user)?;
+        }
+        for role in self.create_roles.iter() {
+            writeln!(f, "+ role {}", role)?;
+        }
+        for group in self.create_groups.iter() {
+            writeln!(f, "+ group {}", group)?;
+        }
+        for change in self.update_groups.iter() {
+            writeln!(f, "~ group {}", change.name)?;
+            for user in change.users.added() {
+                writeln!(f, "    + user {}", user)?;
+            }
+            for user in change.users.removed() {
+                writeln!(f, "    - user {}", user)?;
+            }
+            for role in change.roles.added() {
+                writeln!(f, "    + role {}", role)?;
+            }
+            for role in change.roles.removed() {
+                writeln!(f, "    - role {}", role)?;
+            }
+        }
+        for missing_user in self.skipped_users() {
+            writeln!(f, "! user {} does not exist in Galaxy, skipped ({})", missing_user.email, join(&missing_user.groups))?;
+        }
+        for reset in self.unsent_password_resets.iter() {
+            writeln!(f, "! password reset {} not sent, retried by the next apply: {}", reset.email, reset.error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set<T: std::str::FromStr + Ord>(items: &[&str]) -> BTreeSet<T> where T::Err: fmt::Debug {
+        items.iter().map(|item| item.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_set_change() {
+        let change: SetChange<Email> = SetChange::new(set(&["a@example.com", "b@example.com"]), set(&["b@example.com", "c@example.com"]));
+        assert_eq!(change.added().map(|e| e.to_string()).collect::<Vec<_>>(), vec!["c@example.com"]);
+        assert_eq!(change.removed().map(|e| e.to_string()).collect::<Vec<_>>(), vec!["a@example.com"]);
+        assert!(!change.is_unchanged());
+    }
+
+    #[test]
+    fn test_empty_plan() {
+        let plan = Plan::default();
+        assert!(plan.is_empty());
+        assert_eq!(plan.to_string(), "No changes.\n");
+    }
+
+    #[test]
+    fn test_plan_display_and_json() {
+        let plan = Plan {
+            create_roles: vec!["training".parse().unwrap()],
+            update_groups: vec![GroupChange {
+                name: "team_a".parse().unwrap(),
+                id: Some("1".parse().unwrap()),
+                users: SetChange::new(set(&["a@example.com"]), set(&["b@example.com"])),
+                roles: SetChange::new(set(&[]), set(&["training"])),
+            }],
+            user_ids: HashMap::from([("b@example.com".parse().unwrap(), "2".parse().unwrap())]),
+            ..Default::default()
+        };
+        assert_eq!(plan.to_string(), "+ role training\n~ group team_a\n    + user b@example.com\n    - user a@example.com\n    + role training\n");
+        let json: serde_json::Value = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["create_roles"][0], "training");
+        assert_eq!(json["update_groups"][0]["users"]["before"][0], "a@example.com");
+        assert!(json.get("user_ids").is_none());
+    }
+
+    #[test]
+    fn test_missing_users() {
+        let missing_user = MissingUser {
+            email: "c@example.com".parse().unwrap(),
+            groups: vec!["team_a".parse().unwrap(), "team_b".parse().unwrap()],
+            action: MissingUserAction::Skipped,
+        };
+        let plan = Plan {
+            missing_users: vec![missing_user.clone()],
+            ..Default::default()
+        };
+        assert!(plan.is_empty());
+        assert_eq!(plan.to_string(), "No changes.\n! user c@example.com does not exist in Galaxy, skipped (team_a, team_b)\n");
+        let json: serde_json::Value = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["missing_users"][0]["action"], "skipped");
+
+        let error = MissingUsersError { missing_users: vec![missing_user] };
+        assert_eq!(error.to_string(), "1 user(s) do not exist in Galaxy: c@example.com (team_a, team_b)");
+    }
+}
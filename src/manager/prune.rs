@@ -0,0 +1,82 @@
+//! # Prune Module
+//!
+//! A `PrunePlan` lists the groups created by the training manager that are no longer
+//! in the configuration. `TrainingManager::prune` removes their users and roles, then
+//! deletes them, so their members lose the training roles right away.
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use crate::galaxy::types::*;
+
+/// The managed groups to delete.
+#[derive(Debug, Default, Serialize)]
+pub struct PrunePlan {
+    pub delete_groups: Vec<PrunedGroup>,
+    /// Managed groups already deleted from Galaxy, to remove from the state file.
+    #[serde(skip)]
+    pub(crate) forget_groups: Vec<GroupName>,
+    /// State file the managed groups are recorded in.
+    #[serde(skip)]
+    pub(crate) state: Option<PathBuf>,
+}
+
+/// A managed group with its current users and roles.
+#[derive(Debug, Serialize)]
+pub struct PrunedGroup {
+    pub name: GroupName,
+    pub id: GroupID,
+    pub users: BTreeSet<Email>,
+    pub roles: BTreeSet<RoleName>,
+}
+
+impl PrunePlan {
+    /// Returns `true` if pruning would not send any mutating request.
+    pub fn is_empty(&self) -> bool {
+        self.delete_groups.is_empty()
+    }
+}
+
+impl Display for PrunePlan {
+    /// Formats the plan as a human readable diff.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            writeln!(f, "No groups to prune.")?;
+        }
+        for group in self.delete_groups.iter() {
+            writeln!(f, "- group {}", group.name)?;
+            for user in group.users.iter() {
+                writeln!(f, "    - user {}", user)?;
+            }
+            for role in group.roles.iter() {
+                writeln!(f, "    - role {}", role)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_plan_display_and_json() {
+        assert_eq!(PrunePlan::default().to_string(), "No groups to prune.\n");
+
+        let plan = PrunePlan {
+            delete_groups: vec![PrunedGroup {
+                name: "team_a".parse().unwrap(),
+                id: "1".parse().unwrap(),
+                users: BTreeSet::from(["a@example.com".parse().unwrap()]),
+                roles: BTreeSet::from(["training".parse().unwrap()]),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(plan.to_string(), "- group team_a\n    - user a@example.com\n    - role training\n");
+        let json: serde_json::Value = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["delete_groups"][0]["name"], "team_a");
+        assert!(json.get("state").is_none());
+    }
+}
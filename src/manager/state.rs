@@ -0,0 +1,88 @@
+//! # State Module
+//!
+//! Galaxy has no way to tag a group, so the groups created by `TrainingManager::apply`
+//! are recorded in a JSON state file. Only the groups it lists are ever deleted by
+//! `prune`; groups that existed before the training manager was set up are left alone.
+//! Roles are shared by the groups and never deleted, so they are not recorded.
+//!
+//! The state file also records the members `apply` added to every group, so the
+//! `preserve` reconcile mode removes them once they are no longer configured, and
+//! leaves the members added by hand alone.
+//!
+//! Users whose password reset email could not be sent when `apply` created them are
+//! recorded too, so the next `apply` sends it again instead of leaving them locked out.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use crate::galaxy::types::*;
+
+/// The groups owned by the training manager, and the members it added.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub groups: BTreeSet<GroupName>,
+    /// Configured members added by the training manager, per group.
+    #[serde(default)]
+    pub members: BTreeMap<GroupName, BTreeSet<Email>>,
+    /// Created users whose password reset email is still to be sent.
+    #[serde(default)]
+    pub password_resets: BTreeSet<Email>,
+}
+
+impl State {
+    /// Reads the state file at `path`, an empty state if it does not exist yet.
+    pub fn load(path: &Path) -> Result<State> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).with_context(|| format!("invalid state file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e).with_context(|| format!("cannot read state file {}", path.display())),
+        }
+    }
+
+    /// Writes the state file at `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("cannot write state file {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path).with_context(|| format!("cannot write state file {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_save() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("training-manager-state-{}.json", std::process::id()));
+        assert_eq!(State::load(&path)?, State::default());
+
+        let state = State {
+            groups: BTreeSet::from(["team_a".parse()?]),
+            members: BTreeMap::from([("team_a".parse()?, BTreeSet::from(["user1@email.com".parse()?]))]),
+            password_resets: BTreeSet::from(["user2@email.com".parse()?]),
+        };
+        state.save(&path)?;
+        let loaded = State::load(&path);
+        std::fs::write(&path, "not json")?;
+        let invalid = State::load(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded?, state);
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_recorded_roles() -> Result<()> {
+        // State files written before roles stopped being recorded still load.
+        let path = std::env::temp_dir().join(format!("training-manager-state-roles-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"groups": ["team_a"], "roles": ["training"]}"#)?;
+        let loaded = State::load(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded?.groups, BTreeSet::from(["team_a".parse()?]));
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! # Status Module
+//!
+//! Read-only report of which configured groups are in training and who is in them.
+
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Serialize;
+use std::fmt::{self, Display};
+use crate::config::TimeRange;
+use crate::galaxy::types::*;
+
+const WINDOW_FORMAT: &str = "%Y-%m-%d %H:%M %:z";
+
+/// The bounds of a schedule window, in the window's timezone.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct WindowStatus {
+    /// Start of the window as written in the configuration.
+    pub nominal_start: DateTime<FixedOffset>,
+    /// End of the window as written in the configuration, excluded.
+    pub nominal_end: DateTime<FixedOffset>,
+    /// Start of the window widened by its lead.
+    pub effective_start: DateTime<FixedOffset>,
+    /// End of the window widened by its lag, excluded.
+    pub effective_end: DateTime<FixedOffset>,
+}
+
+impl From<&TimeRange> for WindowStatus {
+    fn from(time_range: &TimeRange) -> Self {
+        let local = |instant: DateTime<Utc>| instant.with_timezone(&time_range.timezone).fixed_offset();
+        WindowStatus {
+            nominal_start: local(time_range.start()),
+            nominal_end: local(time_range.end()),
+            effective_start: local(time_range.effective_start()),
+            effective_end: local(time_range.effective_end()),
+        }
+    }
+}
+
+/// The state of a configured group at a given instant.
+#[derive(Debug, Serialize)]
+pub struct GroupStatus {
+    pub name: GroupName,
+    /// `true` if one of the group's schedule items contains the instant.
+    pub in_training: bool,
+    /// The window containing the instant, or the next one.
+    pub window: Option<WindowStatus>,
+    /// Members of the Galaxy group, empty if the group does not exist yet.
+    pub users: Vec<Email>,
+}
+
+impl Display for GroupStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.in_training { "in training" } else { "not in training" };
+        writeln!(f, "{}: {}", self.name, state)?;
+        if let Some(window) = &self.window {
+            writeln!(f, "    window: {} to {}", window.nominal_start.format(WINDOW_FORMAT), window.nominal_end.format(WINDOW_FORMAT))?;
+            writeln!(f, "    effective: {} to {}", window.effective_start.format(WINDOW_FORMAT), window.effective_end.format(WINDOW_FORMAT))?;
+        }
+        for user in self.users.iter() {
+            writeln!(f, "    {}", user)?;
+        }
+        Ok(())
+    }
+}